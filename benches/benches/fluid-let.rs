@@ -26,6 +26,22 @@ fn get(c: &mut Criterion) {
             b.iter(|| COUNTER.get(|value| read_and_add(&mut total, value.unwrap_or(&0))));
         });
     });
+    group.bench_function(BenchmarkId::new("get", "global"), |b| {
+        fluid_let!(static COUNTER: i32);
+        let mut total = 0;
+        COUNTER.set_global_default(1);
+        b.iter(|| COUNTER.get(|value| read_and_add(&mut total, value.unwrap_or(&0))));
+        COUNTER.reset_global_default();
+    });
+    group.bench_function(BenchmarkId::new("get", "global-shadowed"), |b| {
+        fluid_let!(static COUNTER: i32);
+        let mut total = 0;
+        COUNTER.set_global_default(2);
+        COUNTER.set(1, || {
+            b.iter(|| COUNTER.get(|value| read_and_add(&mut total, value.unwrap_or(&0))));
+        });
+        COUNTER.reset_global_default();
+    });
     group.bench_function(BenchmarkId::new("get", "static"), |b| {
         static mut COUNTER: Option<&i32> = None;
         let mut total = 0;
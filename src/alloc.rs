@@ -0,0 +1,294 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamically bound allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem;
+use std::ptr;
+
+type Allocator = &'static (dyn GlobalAlloc + Sync);
+
+/// Allocator which made the block, stored right before the returned pointer.
+type Header = Option<Allocator>;
+
+// This is not a DynamicVariable because its storage needs a destructor,
+// and registering one may allocate. Cells initialized with const do not.
+thread_local! {
+    static CURRENT: Cell<Option<Allocator>> = const { Cell::new(None) };
+}
+
+/// Global allocator which can be rebound for a dynamic extent.
+///
+/// Install it as `#[global_allocator]`, then use [`with`](#method.with) to route
+/// allocations made by the current thread to another allocator:
+///
+/// ```
+/// use std::alloc::{GlobalAlloc, Layout, System};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// use fluid_let::FluidAlloc;
+///
+/// #[global_allocator]
+/// static GLOBAL: FluidAlloc = FluidAlloc;
+///
+/// struct Counting {
+///     live: AtomicUsize,
+/// }
+///
+/// unsafe impl GlobalAlloc for Counting {
+///     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+///         self.live.fetch_add(1, Ordering::Relaxed);
+///         System.alloc(layout)
+///     }
+///
+///     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+///         self.live.fetch_sub(1, Ordering::Relaxed);
+///         System.dealloc(ptr, layout)
+///     }
+/// }
+///
+/// static COUNTING: Counting = Counting {
+///     live: AtomicUsize::new(0),
+/// };
+///
+/// fn main() {
+///     let numbers = FluidAlloc::with(&COUNTING, || vec![1, 2, 3]);
+///     assert_eq!(COUNTING.live.load(Ordering::Relaxed), 1);
+///
+///     // Memory is returned to the allocator which has allocated it.
+///     drop(numbers);
+///     assert_eq!(COUNTING.live.load(Ordering::Relaxed), 0);
+/// }
+/// ```
+///
+/// Outside of `with` calls, the [`System`](https://doc.rust-lang.org/std/alloc/struct.System.html)
+/// allocator is used. Each block is prefixed with a small header which remembers its allocator,
+/// so blocks can be freed anywhere, by any thread. This is why bound allocators must be `'static`
+/// and `Sync`.
+///
+/// Allocations made by the bound allocator itself are served by `System`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FluidAlloc;
+
+/// Restores previous allocator binding when dropped.
+struct Restore {
+    old: Option<Allocator>,
+}
+
+impl Restore {
+    fn bind(allocator: Option<Allocator>) -> Self {
+        Restore {
+            old: CURRENT.with(|current| current.replace(allocator)),
+        }
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.old));
+    }
+}
+
+impl FluidAlloc {
+    /// Routes allocations to `allocator` for the dynamic extent of the closure.
+    ///
+    /// This has effect only if `FluidAlloc` is the global allocator.
+    pub fn with<R>(allocator: &'static (impl GlobalAlloc + Sync), f: impl FnOnce() -> R) -> R {
+        let _restore_ = Restore::bind(Some(allocator));
+        f()
+    }
+
+    unsafe fn allocate(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let (block_layout, offset) = match with_header(layout) {
+            Some(layout) => layout,
+            None => return ptr::null_mut(),
+        };
+        let allocator = CURRENT.with(Cell::get);
+        let block = match allocator {
+            Some(allocator) => {
+                let _restore_ = Restore::bind(None);
+                if zeroed {
+                    allocator.alloc_zeroed(block_layout)
+                } else {
+                    allocator.alloc(block_layout)
+                }
+            }
+            None if zeroed => System.alloc_zeroed(block_layout),
+            None => System.alloc(block_layout),
+        };
+        if block.is_null() {
+            return block;
+        }
+        // This is safe because the header fits between the block start and the data,
+        // and it is aligned since the data offset is a multiple of header alignment.
+        let data = block.add(offset);
+        (data.sub(mem::size_of::<Header>()) as *mut Header).write(allocator);
+        data
+    }
+}
+
+/// Returns layout of the block with the header, and the offset of data in it.
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    Layout::new::<Header>().extend(layout).ok()
+}
+
+unsafe impl GlobalAlloc for FluidAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate(layout, false)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.allocate(layout, true)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // This is safe because the same layout has been successfully extended in alloc().
+        let (block_layout, offset) = with_header(layout).unwrap_unchecked();
+        let block = ptr.sub(offset);
+        match (ptr.sub(mem::size_of::<Header>()) as *const Header).read() {
+            Some(allocator) => {
+                let _restore_ = Restore::bind(None);
+                allocator.dealloc(block, block_layout);
+            }
+            None => System.dealloc(block, block_layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let header = (ptr.sub(mem::size_of::<Header>()) as *const Header).read();
+        if !same_allocator(header, CURRENT.with(Cell::get)) {
+            // Memory cannot be moved between allocators, so make a copy.
+            let new_ptr = self.alloc(new_layout);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+            return new_ptr;
+        }
+        // This is safe because the same layout has been successfully extended in alloc().
+        let (block_layout, offset) = with_header(layout).unwrap_unchecked();
+        // Alignment is the same, so the data stays at the same offset after the header.
+        let new_block_size = match with_header(new_layout) {
+            Some((new_block_layout, _)) => new_block_layout.size(),
+            None => return ptr::null_mut(),
+        };
+        let block = ptr.sub(offset);
+        let new_block = match header {
+            Some(allocator) => {
+                let _restore_ = Restore::bind(None);
+                allocator.realloc(block, block_layout, new_block_size)
+            }
+            None => System.realloc(block, block_layout, new_block_size),
+        };
+        if new_block.is_null() {
+            return new_block;
+        }
+        // The header has been moved along with the data.
+        new_block.add(offset)
+    }
+}
+
+/// Returns true if both headers refer to the same allocator.
+fn same_allocator(a: Header, b: Header) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => ptr::eq(a as *const _ as *const u8, b as *const _ as *const u8),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    // Exercise FluidAlloc with all allocations made by tests.
+    #[global_allocator]
+    static GLOBAL: FluidAlloc = FluidAlloc;
+
+    struct Counting {
+        live: AtomicUsize,
+        resized: AtomicUsize,
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.live.fetch_add(1, Ordering::Relaxed);
+            // Nested allocations must not reach this allocator again.
+            assert!(CURRENT.with(Cell::get).is_none());
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.live.fetch_sub(1, Ordering::Relaxed);
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            self.resized.fetch_add(1, Ordering::Relaxed);
+            assert!(CURRENT.with(Cell::get).is_none());
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    static COUNTING: Counting = Counting {
+        live: AtomicUsize::new(0),
+        resized: AtomicUsize::new(0),
+    };
+
+    static GROWING: Counting = Counting {
+        live: AtomicUsize::new(0),
+        resized: AtomicUsize::new(0),
+    };
+
+    #[test]
+    fn routing() {
+        unsafe {
+            let small = Layout::new::<u8>();
+            let large = Layout::from_size_align(256, 64).unwrap();
+
+            let unbound = FluidAlloc.alloc(small);
+            let (bound, zeroed) = FluidAlloc::with(&COUNTING, || {
+                (FluidAlloc.alloc(small), FluidAlloc.alloc_zeroed(large))
+            });
+            assert_eq!(COUNTING.live.load(Ordering::Relaxed), 2);
+            assert_eq!(zeroed as usize % 64, 0);
+            assert!((0..256).all(|i| *zeroed.add(i) == 0));
+
+            FluidAlloc::with(&COUNTING, || FluidAlloc.dealloc(unbound, small));
+            assert_eq!(COUNTING.live.load(Ordering::Relaxed), 2);
+
+            let bound = bound as usize;
+            thread::spawn(move || FluidAlloc.dealloc(bound as *mut u8, small))
+                .join()
+                .unwrap();
+            FluidAlloc.dealloc(zeroed, large);
+            assert_eq!(COUNTING.live.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    #[test]
+    fn resizing() {
+        let mut numbers = FluidAlloc::with(&GROWING, || {
+            let mut numbers = Vec::with_capacity(1);
+            for i in 0..1000 {
+                numbers.push(i);
+            }
+            numbers
+        });
+        assert_eq!(GROWING.live.load(Ordering::Relaxed), 1);
+        assert!(GROWING.resized.load(Ordering::Relaxed) > 0);
+        assert_eq!(numbers, (0..1000).collect::<Vec<_>>());
+
+        // Resizing outside of the binding moves the data to the System allocator.
+        numbers.shrink_to_fit();
+        assert_eq!(GROWING.live.load(Ordering::Relaxed), 0);
+        numbers.push(1000);
+        assert_eq!(numbers, (0..1001).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Cooperative cancellation.
+//!
+//! [`scope`] establishes a cancellation token for the dynamic extent of a closure.
+//! Code running in that extent can [`check`] whether it has been cancelled,
+//! without the token being passed down explicitly:
+//!
+//! ```
+//! use fluid_let::cancel::{self, Cancelled};
+//!
+//! fn process(items: &[u32]) -> Result<u32, Cancelled> {
+//!     let mut sum = 0;
+//!     for item in items {
+//!         cancel::check()?;
+//!         sum += item;
+//!     }
+//!     Ok(sum)
+//! }
+//!
+//! let result = cancel::scope(|token| {
+//!     token.cancel();
+//!     process(&[1, 2, 3])
+//! });
+//! assert_eq!(result, Err(Cancelled));
+//!
+//! // Outside of cancellation scopes, check() always succeeds.
+//! assert_eq!(process(&[1, 2, 3]), Ok(6));
+//! ```
+//!
+//! Nested scopes are linked: cancelling an outer token cancels all inner ones,
+//! but cancelling an inner token does not affect outer ones.
+//!
+//! Just like dynamic variables, the current token is thread-local. Tokens can be cloned
+//! and sent to other threads, where [`attach`] makes them current:
+//!
+//! ```
+//! use std::thread;
+//!
+//! use fluid_let::cancel;
+//!
+//! cancel::scope(|token| {
+//!     let token = token.clone();
+//!     let worker = thread::spawn(move || {
+//!         cancel::attach(&token, || {
+//!             while cancel::check().is_ok() {
+//!                 thread::yield_now();
+//!             }
+//!         })
+//!     });
+//!     cancel::current().unwrap().cancel();
+//!     worker.join().unwrap();
+//! });
+//! ```
+//!
+//! [`scope`]: fn.scope.html
+//! [`check`]: fn.check.html
+//! [`attach`]: fn.attach.html
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::fluid_let;
+
+/// Cancellation token.
+///
+/// Tokens are cheap to clone. All clones refer to the same token.
+#[derive(Debug, Clone)]
+pub struct Token {
+    node: Arc<Node>,
+}
+
+#[derive(Debug)]
+struct Node {
+    cancelled: AtomicBool,
+    parent: Option<Arc<Node>>,
+}
+
+/// Error returned by [`check`](fn.check.html) when the current token is cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("operation has been cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+fluid_let!(static CURRENT: Token);
+
+impl Token {
+    /// Makes a new token, cancelled along with the parent.
+    fn new(parent: Option<&Token>) -> Self {
+        Token {
+            node: Arc::new(Node {
+                cancelled: AtomicBool::new(false),
+                parent: parent.map(|parent| Arc::clone(&parent.node)),
+            }),
+        }
+    }
+
+    /// Cancels this token and all tokens of nested scopes.
+    pub fn cancel(&self) {
+        self.node.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Returns true if this token or any of its parents has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        let mut node = Some(&self.node);
+        while let Some(current) = node {
+            if current.cancelled.load(Ordering::Acquire) {
+                return true;
+            }
+            node = current.parent.as_ref();
+        }
+        false
+    }
+
+    /// Returns `Err(Cancelled)` if the token has been cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Establishes a new cancellation scope for the dynamic extent of the closure.
+///
+/// The closure receives the token of the new scope. It is cancelled along with
+/// the token of the enclosing scope, if any.
+pub fn scope<R>(f: impl FnOnce(&Token) -> R) -> R {
+    let token = CURRENT.get(Token::new);
+    CURRENT.set(&token, || f(&token))
+}
+
+/// Makes an existing token current for the dynamic extent of the closure.
+///
+/// Use this to propagate cancellation to other threads.
+pub fn attach<R>(token: &Token, f: impl FnOnce() -> R) -> R {
+    CURRENT.set(token, f)
+}
+
+/// Returns the token of the current cancellation scope.
+pub fn current() -> Option<Token> {
+    CURRENT.cloned()
+}
+
+/// Checks whether the current cancellation scope has been cancelled.
+///
+/// Always succeeds outside of cancellation scopes.
+pub fn check() -> Result<(), Cancelled> {
+    CURRENT.get(|token| token.map_or(Ok(()), Token::check))
+}
+
+/// Returns true if the current cancellation scope has been cancelled.
+pub fn is_cancelled() -> bool {
+    check().is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn nested_scopes() {
+        assert!(!is_cancelled());
+        scope(|outer| {
+            scope(|inner| {
+                inner.cancel();
+                assert_eq!(check(), Err(Cancelled));
+            });
+            assert_eq!(check(), Ok(()));
+            scope(|inner| {
+                outer.cancel();
+                assert!(inner.is_cancelled());
+                assert!(is_cancelled());
+            });
+            assert!(is_cancelled());
+        });
+        assert!(!is_cancelled());
+    }
+
+    #[test]
+    fn other_threads() {
+        scope(|token| {
+            let token = token.clone();
+            let worker = thread::spawn(move || {
+                assert!(current().is_none());
+                attach(&token, || scope(|_| is_cancelled()))
+            });
+            assert!(!worker.join().unwrap());
+            current().unwrap().cancel();
+            let token = current().unwrap();
+            let worker = thread::spawn(move || attach(&token, is_cancelled));
+            assert!(worker.join().unwrap());
+        });
+    }
+}
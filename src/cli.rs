@@ -0,0 +1,358 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Overriding dynamic variables from command line.
+//!
+//! Values of [exported](../registry/index.html) dynamic variables can be set
+//! from command line with `--fluid NAME=VALUE` arguments:
+//!
+//! ```no_run
+//! use fluid_let::{cli, fluid_let, registry};
+//!
+//! fluid_let!(pub static HASH_LENGTH: usize);
+//!
+//! fn main() -> Result<(), cli::Error> {
+//!     registry::export_from_str("hash_length", &HASH_LENGTH);
+//!
+//!     cli::with_args(|args| {
+//!         // HASH_LENGTH is set here if the program is called with
+//!         // "--fluid hash_length=16" or "--fluid=hash_length=16".
+//!         // "args" contain all other arguments.
+//!     })?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Values are parsed with `FromStr` if the variable has been exported with it.
+//! Otherwise, with `"config"` feature, values are deserialized with serde:
+//! if the value is valid JSON then it is used as is, otherwise it is treated as a string.
+//!
+//! `--fluid-list` argument prints names and types of all exported variables
+//! to standard output instead of running the program.
+//!
+//! Arguments after `--` are not processed.
+
+use std::any::Any;
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::registry::{self, Assignment, Variable};
+use crate::SetError;
+
+/// Error in command-line overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// `--fluid` is not followed by an argument.
+    MissingArgument,
+    /// `--fluid` argument is not in `NAME=VALUE` format.
+    InvalidArgument(String),
+    /// `--fluid` argument is not valid Unicode.
+    NotUnicode(OsString),
+    /// There is no exported variable with this name.
+    UnknownVariable(String),
+    /// Value cannot be converted into variable type.
+    InvalidValue {
+        /// Name of the variable.
+        name: String,
+        /// Name of the expected type.
+        expected: &'static str,
+        /// Description of the error.
+        message: String,
+    },
+    /// Value cannot be bound to the variable, e.g., because it does not allow shadowing.
+    Bind {
+        /// Name of the variable.
+        name: String,
+        /// Why the value cannot be bound.
+        error: SetError,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingArgument => write!(f, "--fluid requires an argument"),
+            Error::InvalidArgument(arg) => {
+                write!(f, "--fluid argument must be NAME=VALUE: {:?}", arg)
+            }
+            Error::NotUnicode(arg) => write!(f, "--fluid argument is not valid Unicode: {:?}", arg),
+            Error::UnknownVariable(name) => write!(f, "unknown variable: {}", name),
+            Error::InvalidValue {
+                name,
+                expected,
+                message,
+            } => write!(f, "{}: expected {}: {}", name, expected, message),
+            Error::Bind { name, error } => write!(f, "cannot bind {}: {}", name, error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Bind { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of [`with_args`](fn.with_args.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<R> {
+    /// The closure has been called and returned this value.
+    Ran(R),
+    /// `--fluid-list` has been requested, so variables have been listed
+    /// instead of calling the closure.
+    Listed,
+}
+
+/// Command-line overrides.
+pub struct Overrides {
+    values: Vec<Assignment>,
+    args: Vec<OsString>,
+    list: bool,
+}
+
+impl Overrides {
+    /// Extracts overrides from arguments.
+    ///
+    /// Note that the first argument is usually the program name.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Overrides, Error> {
+        let mut overrides = Overrides {
+            values: Vec::new(),
+            args: Vec::new(),
+            list: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                overrides.args.push(arg);
+                overrides.args.extend(args);
+                break;
+            }
+            if arg == "--fluid-list" {
+                overrides.list = true;
+                continue;
+            }
+            let value = if arg == "--fluid" {
+                args.next().ok_or(Error::MissingArgument)?
+            } else {
+                match arg.to_str().and_then(|s| s.strip_prefix("--fluid=")) {
+                    Some(value) => value.into(),
+                    None => {
+                        overrides.args.push(arg);
+                        continue;
+                    }
+                }
+            };
+            overrides.values.push(assignment(value)?);
+        }
+        Ok(overrides)
+    }
+
+    /// Arguments with overrides removed.
+    pub fn args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// Returns true if `--fluid-list` was requested.
+    pub fn list_requested(&self) -> bool {
+        self.list
+    }
+
+    /// Binds overridden variables for the extent of the closure.
+    ///
+    /// If a variable is overridden multiple times, the last value is used.
+    /// If some variable cannot be bound, the closure is not called.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> Result<R, Error> {
+        registry::bind_all(&self.values, f).map_err(|(name, error)| Error::Bind {
+            name: name.to_owned(),
+            error,
+        })
+    }
+}
+
+fn assignment(arg: OsString) -> Result<Assignment, Error> {
+    let arg = arg.into_string().map_err(Error::NotUnicode)?;
+    let (name, value) = match arg.find('=') {
+        Some(index) => (&arg[..index], &arg[index + 1..]),
+        None => return Err(Error::InvalidArgument(arg)),
+    };
+    let variable = registry::lookup(name).ok_or_else(|| Error::UnknownVariable(name.to_owned()))?;
+    match parse(&variable, value).and_then(|value| variable.binding.validate(value)) {
+        Ok(value) => Ok((variable, value)),
+        Err(message) => Err(Error::InvalidValue {
+            name: name.to_owned(),
+            expected: variable.type_name,
+            message,
+        }),
+    }
+}
+
+fn parse(variable: &Variable, value: &str) -> Result<Box<dyn Any>, String> {
+    if let Some(parse) = variable.parse {
+        return parse(value);
+    }
+    #[cfg(feature = "config")]
+    {
+        if let Some(deserialize) = variable.deserialize {
+            let value = serde_json::from_str(value)
+                .unwrap_or_else(|_| serde_json::Value::String(value.to_owned()));
+            return deserialize(value);
+        }
+    }
+    Err("variable cannot be parsed from string".to_owned())
+}
+
+/// Writes names and types of all exported variables.
+pub fn write_list(out: &mut impl Write) -> io::Result<()> {
+    for variable in registry::all() {
+        writeln!(out, "{}: {}", variable.name, variable.type_name)?;
+    }
+    Ok(())
+}
+
+/// Binds overridden variables from program arguments for the extent of the closure.
+///
+/// The closure receives remaining arguments. If `--fluid-list` is requested,
+/// the list of exported variables is printed to standard output instead,
+/// and the closure is not called.
+pub fn with_args<R>(f: impl FnOnce(&[OsString]) -> R) -> Result<Outcome<R>, Error> {
+    let overrides = Overrides::parse(std::env::args_os())?;
+    if overrides.list_requested() {
+        let stdout = io::stdout();
+        let _ = write_list(&mut stdout.lock());
+        return Ok(Outcome::Listed);
+    }
+    overrides.run(|| f(overrides.args())).map(Outcome::Ran)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::any::type_name;
+
+    use crate::{fluid_let, InvalidValue};
+
+    fn positive(value: &i32) -> Result<Option<i32>, InvalidValue> {
+        match *value > 0 {
+            true => Ok(None),
+            false => Err(InvalidValue::new("must be positive")),
+        }
+    }
+
+    fluid_let! {
+        static NUMBER: i32;
+        static NAME: String;
+        static POSITIVE: i32; guard = positive;
+        static DENIED: i32; shadow = deny;
+        static FINAL: i32;
+    }
+
+    fn export() {
+        registry::export_from_str("cli::number", &NUMBER);
+        registry::export_from_str("cli::name", &NAME);
+        registry::export_from_str("cli::positive", &POSITIVE);
+        registry::export_from_str("cli::denied", &DENIED);
+        registry::export_from_str("cli::final", &FINAL);
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn overrides() {
+        export();
+
+        let overrides = Overrides::parse(args(&[
+            "program",
+            "--fluid",
+            "cli::number=42",
+            "--verbose",
+            "--fluid=cli::name=a=b",
+            "--",
+            "--fluid",
+            "ignored",
+        ]))
+        .unwrap();
+        assert_eq!(
+            overrides.args(),
+            &args(&["program", "--verbose", "--", "--fluid", "ignored"])[..]
+        );
+        assert!(!overrides.list_requested());
+        let result = overrides.run(|| (NUMBER.copied(), NAME.cloned()));
+        assert_eq!(result, Ok((Some(42), Some("a=b".to_owned()))));
+        assert_eq!(NUMBER.copied(), None);
+    }
+
+    #[test]
+    fn errors() {
+        export();
+
+        let error = |arguments| Overrides::parse(args(arguments)).err();
+        assert_eq!(error(&["--fluid"]), Some(Error::MissingArgument));
+        assert_eq!(
+            error(&["--fluid", "cli::number"]),
+            Some(Error::InvalidArgument("cli::number".to_owned()))
+        );
+        assert_eq!(
+            error(&["--fluid", "cli::missing=1"]),
+            Some(Error::UnknownVariable("cli::missing".to_owned()))
+        );
+        assert!(matches!(
+            error(&["--fluid", "cli::number=one"]),
+            Some(Error::InvalidValue { expected, .. }) if expected == type_name::<i32>()
+        ));
+        assert_eq!(
+            error(&["--fluid", "cli::positive=-1"]),
+            Some(Error::InvalidValue {
+                name: "cli::positive".to_owned(),
+                expected: type_name::<i32>(),
+                message: "must be positive".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn bind_errors() {
+        export();
+
+        let overrides = Overrides::parse(args(&["--fluid=cli::denied=1"])).unwrap();
+        assert_eq!(overrides.run(|| DENIED.copied()), Ok(Some(1)));
+        assert_eq!(
+            DENIED.set(0, || overrides.run(|| unreachable!())),
+            Err(Error::Bind {
+                name: "cli::denied".to_owned(),
+                error: SetError::Shadowing,
+            })
+        );
+
+        let overrides = Overrides::parse(args(&["--fluid=cli::final=1"])).unwrap();
+        assert_eq!(
+            FINAL.set_final(0, || overrides.run(|| unreachable!())),
+            Err(Error::Bind {
+                name: "cli::final".to_owned(),
+                error: SetError::Final,
+            })
+        );
+    }
+
+    #[test]
+    fn listing() {
+        export();
+
+        let overrides = Overrides::parse(args(&["--fluid-list"])).unwrap();
+        assert!(overrides.list_requested());
+
+        let mut out = Vec::new();
+        write_list(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("cli::number: {}\n", type_name::<i32>())));
+        assert!(out.contains(&format!("cli::name: {}\n", type_name::<String>())));
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Conditions and restarts.
+//!
+//! This is a take on the Common Lisp condition system, which lets callers decide how
+//! errors detected deep in the callee are handled, without unwinding the stack first.
+//!
+//! Code which detects a problem _signals_ a condition. Code which knows how to recover
+//! establishes _restarts_ around it. Callers which know what recovery is appropriate
+//! bind _handlers_ which choose a restart:
+//!
+//! ```
+//! use fluid_let::condition::{handler_bind, invoke_restart, restart_case, signal};
+//!
+//! struct MalformedLine(String);
+//!
+//! fn parse_line(line: &str) -> Option<i32> {
+//!     restart_case(
+//!         &[
+//!             ("skip", &|_| None),
+//!             ("use-value", &|value| value.downcast::<i32>().ok().map(|v| *v)),
+//!         ],
+//!         || match line.parse() {
+//!             Ok(value) => Some(value),
+//!             Err(_) => {
+//!                 signal(&MalformedLine(line.to_owned()));
+//!                 panic!("unhandled malformed line: {}", line);
+//!             }
+//!         },
+//!     )
+//! }
+//!
+//! fn parse(text: &str) -> Vec<i32> {
+//!     text.lines().filter_map(parse_line).collect()
+//! }
+//!
+//! // Skip malformed lines.
+//! let numbers = handler_bind(
+//!     |_: &MalformedLine| invoke_restart("skip", ()),
+//!     || parse("1\nx\n3"),
+//! );
+//! assert_eq!(numbers, [1, 3]);
+//!
+//! // Replace malformed lines with zeros.
+//! let numbers = handler_bind(
+//!     |_: &MalformedLine| invoke_restart("use-value", 0),
+//!     || parse("1\nx\n3"),
+//! );
+//! assert_eq!(numbers, [1, 0, 3]);
+//! ```
+//!
+//! Handlers are found by walking the dynamic stack of [`handler_bind`] calls, innermost first.
+//! A handler _declines_ by returning, then outer handlers are tried. While a handler runs,
+//! only outer handlers are active. If all handlers decline, [`signal`] returns.
+//!
+//! Invoking a restart transfers control to the corresponding [`restart_case`] call,
+//! which returns the result of the restart function. This is implemented with unwinding,
+//! so the condition system does not work with `panic = "abort"`.
+//!
+//! [`handler_bind`]: fn.handler_bind.html
+//! [`signal`]: fn.signal.html
+//! [`restart_case`]: fn.restart_case.html
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fluid_let;
+
+/// Active handlers, innermost first.
+#[derive(Clone, Copy)]
+struct Handlers<'a> {
+    frame: Option<&'a HandlerFrame<'a>>,
+}
+
+struct HandlerFrame<'a> {
+    handler: &'a dyn Fn(&dyn Any),
+    parent: Handlers<'a>,
+}
+
+/// Active restarts, innermost first.
+#[derive(Clone, Copy)]
+struct Restarts<'a> {
+    frame: Option<&'a RestartFrame<'a>>,
+}
+
+struct RestartFrame<'a> {
+    id: usize,
+    find: &'a dyn Fn(&str) -> Option<usize>,
+    parent: Restarts<'a>,
+}
+
+fluid_let! {
+    static HANDLERS: for<'a> Handlers<'a>;
+    static RESTARTS: for<'a> Restarts<'a>;
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Unwinding payload of an invoked restart.
+struct Unwind {
+    id: usize,
+    index: usize,
+    value: Box<dyn Any + Send>,
+}
+
+/// Restart function, receiving the value passed to [`invoke_restart`](fn.invoke_restart.html).
+pub type Restart<'a, R> = &'a dyn Fn(Box<dyn Any + Send>) -> R;
+
+/// Binds a handler of conditions of type `C` for the dynamic extent of the closure.
+pub fn handler_bind<C: 'static, R>(handler: impl Fn(&C), body: impl FnOnce() -> R) -> R {
+    let handler = |condition: &dyn Any| {
+        if let Some(condition) = condition.downcast_ref::<C>() {
+            handler(condition);
+        }
+    };
+    HANDLERS.get(|handlers| {
+        let frame = HandlerFrame {
+            handler: &handler,
+            parent: handlers.copied().unwrap_or(Handlers { frame: None }),
+        };
+        HANDLERS.set(
+            Handlers {
+                frame: Some(&frame),
+            },
+            body,
+        )
+    })
+}
+
+/// Signals a condition.
+///
+/// Calls handlers of conditions of type `C`, innermost first.
+/// Returns if all of them decline to handle the condition.
+pub fn signal<C: 'static>(condition: &C) {
+    HANDLERS.get(|handlers| {
+        let mut frame = handlers.and_then(|handlers| handlers.frame);
+        while let Some(current) = frame {
+            HANDLERS.set(current.parent, || (current.handler)(condition));
+            frame = current.parent.frame;
+        }
+    })
+}
+
+/// Establishes restarts for the dynamic extent of the closure.
+///
+/// If one of the restarts is invoked, the result of its function is returned.
+pub fn restart_case<R>(restarts: &[(&str, Restart<'_, R>)], body: impl FnOnce() -> R) -> R {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let find = |name: &str| restarts.iter().position(|(n, _)| *n == name);
+    let result = RESTARTS.get(|parent| {
+        let frame = RestartFrame {
+            id,
+            find: &find,
+            parent: parent.copied().unwrap_or(Restarts { frame: None }),
+        };
+        let frame = Restarts {
+            frame: Some(&frame),
+        };
+        panic::catch_unwind(AssertUnwindSafe(|| RESTARTS.set(frame, body)))
+    });
+    match result {
+        Ok(result) => result,
+        Err(payload) => match payload.downcast::<Unwind>() {
+            Ok(unwind) if unwind.id == id => (restarts[unwind.index].1)(unwind.value),
+            Ok(unwind) => panic::resume_unwind(unwind),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// Returns true if a restart with this name is active.
+pub fn find_restart(name: &str) -> bool {
+    lookup(name).is_some()
+}
+
+/// Invokes the innermost active restart with this name, passing a value to it.
+///
+/// # Panics
+///
+/// If there is no active restart with this name.
+pub fn invoke_restart<A: Any + Send>(name: &str, value: A) -> ! {
+    match lookup(name) {
+        Some((id, index)) => panic::resume_unwind(Box::new(Unwind {
+            id,
+            index,
+            value: Box::new(value),
+        })),
+        None => panic!("no active restart named {:?}", name),
+    }
+}
+
+fn lookup(name: &str) -> Option<(usize, usize)> {
+    RESTARTS.get(|restarts| {
+        let mut frame = restarts.and_then(|restarts| restarts.frame);
+        while let Some(current) = frame {
+            if let Some(index) = (current.find)(name) {
+                return Some((current.id, index));
+            }
+            frame = current.parent.frame;
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    struct Warning(&'static str);
+
+    #[test]
+    fn handlers_decline() {
+        let log = RefCell::new(Vec::new());
+        signal(&Warning("unhandled"));
+        handler_bind(
+            |w: &Warning| log.borrow_mut().push(format!("outer {}", w.0)),
+            || {
+                handler_bind(
+                    |w: &Warning| {
+                        log.borrow_mut().push(format!("inner {}", w.0));
+                        signal(&Warning("nested"));
+                    },
+                    || {
+                        handler_bind(|_: &i32| unreachable!(), || signal(&Warning("first")));
+                    },
+                );
+            },
+        );
+        assert_eq!(
+            *log.borrow(),
+            ["inner first", "outer nested", "outer first"]
+        );
+    }
+
+    #[test]
+    fn restarts() {
+        let compute = |value: i32| {
+            restart_case(
+                &[
+                    ("retry", &|_| -1),
+                    ("use-value", &|v| *v.downcast::<i32>().unwrap()),
+                ],
+                || {
+                    assert!(find_restart("use-value"));
+                    restart_case(&[("inner", &|_| 0)], || {
+                        signal(&value);
+                        value
+                    })
+                },
+            )
+        };
+        assert!(!find_restart("use-value"));
+        assert_eq!(compute(1), 1);
+        let result = handler_bind(
+            |value: &i32| {
+                if *value > 10 {
+                    invoke_restart("use-value", 10)
+                }
+            },
+            || (compute(5), compute(50)),
+        );
+        assert_eq!(result, (5, 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "no active restart named \"missing\"")]
+    fn missing_restart() {
+        invoke_restart("missing", ());
+    }
+}
@@ -0,0 +1,710 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Binding dynamic variables from configuration files.
+//!
+//! Values of [exported](../registry/index.html) dynamic variables can be loaded
+//! from a configuration file in TOML or JSON format:
+//!
+//! ```no_run
+//! use fluid_let::{config, fluid_let, registry};
+//!
+//! fluid_let! {
+//!     pub static HASH_LENGTH: usize;
+//!     pub static LOG_LEVEL: String;
+//! }
+//!
+//! fn main() -> Result<(), config::Error> {
+//!     registry::export("hash_length", &HASH_LENGTH);
+//!     registry::export("log.level", &LOG_LEVEL);
+//!
+//!     config::with_file("app.toml", || {
+//!         // HASH_LENGTH and LOG_LEVEL are set here
+//!     })
+//! }
+//! ```
+//!
+//! Keys of the configuration file are matched to names of exported variables.
+//! Nested tables are matched to dotted names. For example, this file
+//!
+//! ```toml
+//! hash_length = 16
+//!
+//! [log]
+//! level = "debug"
+//! ```
+//!
+//! sets `HASH_LENGTH` to `16` and `LOG_LEVEL` to `"debug"`.
+//!
+//! Values are deserialized with serde. Variables exported with `FromStr` parser accept
+//! string values. If there are unknown keys in the file or values of incorrect type
+//! then no variables are set and an [`Error`](enum.Error.html) is returned,
+//! describing all problems found.
+//!
+//! # Reloadable defaults
+//!
+//! Long-running programs can use configuration files as a source of process-wide
+//! default values instead. These are used by all threads when there is no value bound
+//! to the variable, and can be reloaded at runtime:
+//!
+//! ```no_run
+//! # use fluid_let::{config, fluid_let, registry};
+//! #
+//! # fluid_let!(pub static HASH_LENGTH: usize);
+//! #
+//! # fn main() -> Result<(), config::Error> {
+//! # registry::export("hash_length", &HASH_LENGTH);
+//! use std::time::Duration;
+//!
+//! let defaults = config::Defaults::load("app.toml")?;
+//!
+//! // Reload defaults whenever the file changes.
+//! let _watcher = defaults.watch(Duration::from_secs(1), |error| {
+//!     eprintln!("failed to reload configuration: {}", error);
+//! });
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Values explicitly bound to variables, as well as default values set by the program with
+//! [`set_global_default`](../struct.DynamicVariable.html#method.set_global_default),
+//! take precedence over defaults from configuration files and are not affected
+//! by reloads. Defaults are updated only if the whole file is valid, and all at once.
+//! Use [`with_snapshot`](fn.with_snapshot.html) to read several of them consistently
+//! while they might be reloaded.
+//!
+//! This module is available only with `"config"` feature.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::registry::{self, Assignment, Variable};
+use crate::shared::Shared;
+use crate::SetError;
+
+/// Format of configuration files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// [TOML](https://toml.io) format.
+    Toml,
+    /// [JSON](https://www.json.org) format.
+    Json,
+}
+
+impl Format {
+    /// Guesses the format from file extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Error in loading configuration.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Configuration file cannot be read.
+    Io(io::Error),
+    /// Format of configuration file is not known.
+    UnknownFormat,
+    /// Configuration file is not valid TOML or JSON.
+    Syntax(String),
+    /// Configuration file is syntactically valid but does not match exported variables.
+    Invalid(Vec<Problem>),
+    /// Value cannot be bound to the variable, e.g., because it does not allow shadowing.
+    Bind {
+        /// Name of the variable.
+        name: String,
+        /// Why the value cannot be bound.
+        error: SetError,
+    },
+}
+
+/// Problem with a configuration key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Full dotted name of the key.
+    pub key: String,
+    /// What is wrong with it.
+    pub kind: ProblemKind,
+}
+
+/// Kind of problem with a configuration key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProblemKind {
+    /// There is no exported variable with this name.
+    UnknownKey,
+    /// Value cannot be converted into variable type.
+    TypeMismatch {
+        /// Name of the expected type.
+        expected: &'static str,
+        /// Description of the error.
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "cannot read configuration: {}", e),
+            Error::UnknownFormat => write!(f, "unknown configuration format"),
+            Error::Syntax(message) => write!(f, "invalid configuration: {}", message),
+            Error::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            Error::Bind { name, error } => write!(f, "cannot bind {}: {}", name, error),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ProblemKind::UnknownKey => write!(f, "{}: unknown key", self.key),
+            ProblemKind::TypeMismatch { expected, message } => {
+                write!(f, "{}: expected {}: {}", self.key, expected, message)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Bind { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Binds dynamic variables from a configuration file for the extent of the closure.
+///
+/// File format is chosen based on its extension: `.toml` or `.json`.
+pub fn with_file<R>(path: impl AsRef<Path>, f: impl FnOnce() -> R) -> Result<R, Error> {
+    let path = path.as_ref();
+    let format = Format::from_path(path).ok_or(Error::UnknownFormat)?;
+    let text = fs::read_to_string(path)?;
+    with_str(&text, format, f)
+}
+
+/// Binds dynamic variables from a configuration string for the extent of the closure.
+pub fn with_str<R>(text: &str, format: Format, f: impl FnOnce() -> R) -> Result<R, Error> {
+    let values = load(text, format)?;
+    registry::bind_all(&values, f).map_err(|(name, error)| Error::Bind {
+        name: name.to_owned(),
+        error,
+    })
+}
+
+/// Process-wide default values backed by a configuration file.
+///
+/// See [module-level documentation](index.html) for examples.
+#[derive(Clone)]
+pub struct Defaults {
+    inner: Arc<DefaultsInner>,
+}
+
+struct DefaultsInner {
+    path: PathBuf,
+    format: Format,
+    state: Mutex<DefaultsState>,
+}
+
+#[derive(Default)]
+struct DefaultsState {
+    // Modification time alone is too coarse to notice quick successive writes.
+    contents: Option<String>,
+    assigned: Vec<Variable>,
+}
+
+impl Defaults {
+    /// Loads default values from a configuration file.
+    ///
+    /// File format is chosen based on its extension: `.toml` or `.json`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Defaults, Error> {
+        let path = path.as_ref();
+        let format = Format::from_path(path).ok_or(Error::UnknownFormat)?;
+        let defaults = Defaults {
+            inner: Arc::new(DefaultsInner {
+                path: path.to_owned(),
+                format,
+                state: Mutex::new(DefaultsState::default()),
+            }),
+        };
+        defaults.reload()?;
+        Ok(defaults)
+    }
+
+    /// Reloads default values from the configuration file.
+    ///
+    /// If the file is not valid, default values are left unchanged.
+    /// Variables which are no longer present in the file lose their default values.
+    ///
+    /// All values are replaced at once: other threads see either old or new values
+    /// of all variables, never a mix of them. Use [`with_snapshot`](fn.with_snapshot.html)
+    /// to read several variables consistently.
+    pub fn reload(&self) -> Result<(), Error> {
+        let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        let text = fs::read_to_string(&self.inner.path);
+        state.contents = text.as_ref().ok().cloned();
+        let text = text?;
+        let values = load(&text, self.inner.format)?;
+        let _update_ = UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut snapshot = match SNAPSHOT.load() {
+            Some(snapshot) => Snapshot::clone(&snapshot),
+            None => Snapshot::default(),
+        };
+        for old in &state.assigned {
+            snapshot.values.remove(&(old.binding.address() as usize));
+        }
+        state.assigned = values
+            .iter()
+            .map(|(variable, _)| variable.clone())
+            .collect();
+        for (variable, value) in values {
+            let address = variable.binding.address() as usize;
+            snapshot
+                .values
+                .insert(address, variable.binding.share(value));
+        }
+        SNAPSHOT.store(Some(Arc::new(snapshot)));
+        Ok(())
+    }
+
+    /// Reloads default values whenever the configuration file changes.
+    ///
+    /// The file is read with given interval, in a background thread, and reloaded
+    /// if its contents have changed since the last reload.
+    /// Reload errors are passed to the provided callback. The thread is stopped when
+    /// the returned watcher is dropped.
+    pub fn watch(&self, interval: Duration, on_error: impl Fn(Error) + Send + 'static) -> Watcher {
+        let defaults = self.clone();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let contents = fs::read_to_string(&defaults.inner.path).ok();
+                let changed = {
+                    let state = defaults
+                        .inner
+                        .state
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner());
+                    state.contents != contents
+                };
+                if changed {
+                    if let Err(error) = defaults.reload() {
+                        on_error(error);
+                    }
+                }
+            }
+        });
+        Watcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Default values of all variables, published at once by each reload.
+#[derive(Default, Clone)]
+pub(crate) struct Snapshot {
+    values: HashMap<usize, Arc<dyn Any + Send + Sync>>,
+}
+
+static SNAPSHOT: Shared<Snapshot> = Shared::empty();
+
+// Serializes reloads by different Defaults so that they do not lose each other's values.
+static UPDATE: Mutex<()> = Mutex::new(());
+
+// This is not a DynamicVariable because dynamic variables read their defaults from here.
+thread_local! {
+    static PINNED: RefCell<Option<Arc<Snapshot>>> = const { RefCell::new(None) };
+}
+
+impl Snapshot {
+    /// Returns the snapshot in effect for the current thread, if any.
+    pub(crate) fn current() -> Option<Arc<Snapshot>> {
+        PINNED
+            .try_with(|pinned| pinned.borrow().clone())
+            .ok()
+            .flatten()
+            .or_else(|| SNAPSHOT.load())
+    }
+
+    /// Returns the default value of the variable at given address.
+    pub(crate) fn get<T: ?Sized + 'static>(&self, address: usize) -> Option<&T> {
+        let value = self.values.get(&address)?;
+        value.downcast_ref::<Box<T>>().map(|value| &**value)
+    }
+}
+
+/// Unpins the snapshot when dropped.
+struct Pinned;
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        PINNED.with(|pinned| *pinned.borrow_mut() = None);
+    }
+}
+
+/// Reads default values from the same reload for the dynamic extent of the closure.
+///
+/// Defaults reloaded by other threads meanwhile become visible only after the closure
+/// returns. This has no effect on values bound to variables.
+pub fn with_snapshot<R>(f: impl FnOnce() -> R) -> R {
+    if PINNED.with(|pinned| pinned.borrow().is_some()) {
+        return f();
+    }
+    let snapshot = SNAPSHOT.load().unwrap_or_default();
+    PINNED.with(|pinned| *pinned.borrow_mut() = Some(snapshot));
+    let _pinned_ = Pinned;
+    f()
+}
+
+/// Background watcher of configuration file changes.
+///
+/// Created by [`Defaults::watch`](struct.Defaults.html#method.watch).
+/// Stops watching when dropped.
+pub struct Watcher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Parses configuration and matches it to exported variables.
+pub(crate) fn load(text: &str, format: Format) -> Result<Vec<Assignment>, Error> {
+    let root: Value = match format {
+        Format::Toml => toml::from_str(text).map_err(|e| Error::Syntax(e.to_string()))?,
+        Format::Json => serde_json::from_str(text).map_err(|e| Error::Syntax(e.to_string()))?,
+    };
+    let root = match root {
+        Value::Object(root) => root,
+        _ => return Err(Error::Syntax("expected a table at top level".to_owned())),
+    };
+    let mut values = Vec::new();
+    let mut problems = Vec::new();
+    collect("", root, &mut values, &mut problems);
+    if problems.is_empty() {
+        Ok(values)
+    } else {
+        Err(Error::Invalid(problems))
+    }
+}
+
+fn collect(
+    prefix: &str,
+    table: serde_json::Map<String, Value>,
+    values: &mut Vec<Assignment>,
+    problems: &mut Vec<Problem>,
+) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match registry::lookup(&key) {
+            Some(variable) => match deserialize(&variable, value)
+                .and_then(|value| variable.binding.validate(value))
+            {
+                Ok(value) => values.push((variable, value)),
+                Err(message) => problems.push(Problem {
+                    key,
+                    kind: ProblemKind::TypeMismatch {
+                        expected: variable.type_name,
+                        message,
+                    },
+                }),
+            },
+            None => match value {
+                Value::Object(table) => collect(&key, table, values, problems),
+                _ => problems.push(Problem {
+                    key,
+                    kind: ProblemKind::UnknownKey,
+                }),
+            },
+        }
+    }
+}
+
+fn deserialize(variable: &Variable, value: Value) -> Result<Box<dyn Any>, String> {
+    match (variable.deserialize, variable.parse, value) {
+        (Some(deserialize), _, value) => deserialize(value),
+        (None, Some(parse), Value::String(value)) => parse(&value),
+        (None, Some(_), _) => Err("expected a string".to_owned()),
+        (None, None, _) => Err("variable is not exported for deserialization".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::any::type_name;
+    use std::env;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::fluid_let;
+
+    fluid_let! {
+        static NUMBER: i32;
+        static NAME: String;
+    }
+
+    fn export() {
+        registry::export("config::number", &NUMBER);
+        registry::export("config::nested.name", &NAME);
+    }
+
+    #[test]
+    fn toml_and_json() {
+        export();
+
+        let toml = r#"
+            "config::number" = 42
+            ["config::nested"]
+            name = "toml"
+        "#;
+        let result = with_str(toml, Format::Toml, || (NUMBER.copied(), NAME.cloned()));
+        assert_eq!(result.unwrap(), (Some(42), Some("toml".to_owned())));
+
+        let json = r#"{"config::number": 9000, "config::nested.name": "json"}"#;
+        let result = with_str(json, Format::Json, || (NUMBER.copied(), NAME.cloned()));
+        assert_eq!(result.unwrap(), (Some(9000), Some("json".to_owned())));
+
+        assert_eq!(NUMBER.copied(), None);
+    }
+
+    #[test]
+    fn files() {
+        export();
+
+        let path = env::temp_dir().join("fluid-let-config-files.json");
+        fs::write(&path, r#"{"config::number": 1}"#).unwrap();
+        let result = with_file(&path, || NUMBER.copied());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), Some(1));
+
+        let result = with_file("config.ini", || NUMBER.copied());
+        assert!(matches!(result, Err(Error::UnknownFormat)));
+    }
+
+    #[test]
+    fn problems() {
+        export();
+
+        let json =
+            r#"{"config::number": "one", "config::unknown": 2, "config::nested": {"name": "ok"}}"#;
+        let result = with_str(json, Format::Json, || unreachable!());
+        let problems = match result {
+            Err(Error::Invalid(problems)) => problems,
+            _ => panic!("expected validation error"),
+        };
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].key, "config::number");
+        assert!(matches!(
+            problems[0].kind,
+            ProblemKind::TypeMismatch { expected, .. } if expected == type_name::<i32>()
+        ));
+        assert_eq!(problems[1].key, "config::unknown");
+        assert_eq!(problems[1].kind, ProblemKind::UnknownKey);
+
+        let result = with_str("[1, 2, 3]", Format::Json, || unreachable!());
+        assert!(matches!(result, Err(Error::Syntax(_))));
+    }
+
+    fluid_let! {
+        static DENIED: i32; shadow = deny;
+        static FINAL: i32;
+    }
+
+    #[test]
+    fn bind_errors() {
+        registry::export("config::bind_errors.denied", &DENIED);
+        registry::export("config::bind_errors.final", &FINAL);
+
+        let json = r#"{"config::bind_errors": {"denied": 1}}"#;
+        assert_eq!(
+            with_str(json, Format::Json, || DENIED.copied()).unwrap(),
+            Some(1)
+        );
+        let result = DENIED.set(0, || with_str(json, Format::Json, || unreachable!()));
+        match result {
+            Err(Error::Bind { name, error }) => {
+                assert_eq!(name, "config::bind_errors.denied");
+                assert_eq!(error, SetError::Shadowing);
+            }
+            _ => panic!("expected binding error"),
+        }
+
+        let json = r#"{"config::bind_errors": {"final": 1}}"#;
+        let result = FINAL.set_final(0, || with_str(json, Format::Json, || unreachable!()));
+        match result {
+            Err(Error::Bind { name, error }) => {
+                assert_eq!(name, "config::bind_errors.final");
+                assert_eq!(error, SetError::Final);
+            }
+            _ => panic!("expected binding error"),
+        }
+    }
+
+    fluid_let! {
+        static DEFAULT_NUMBER: i32;
+        static DEFAULT_NAME: String;
+    }
+
+    #[test]
+    fn defaults() {
+        registry::export("config::defaults.number", &DEFAULT_NUMBER);
+        registry::export("config::defaults.name", &DEFAULT_NAME);
+
+        let path = env::temp_dir().join("fluid-let-config-defaults.toml");
+        let write = |text: &str| fs::write(&path, text).unwrap();
+
+        write("[\"config::defaults\"]\nnumber = 1\nname = \"one\"\n");
+        let defaults = Defaults::load(&path).unwrap();
+        assert_eq!(DEFAULT_NUMBER.copied(), Some(1));
+        let other_thread = thread::spawn(|| DEFAULT_NAME.cloned()).join().unwrap();
+        assert_eq!(other_thread, Some("one".to_owned()));
+
+        DEFAULT_NUMBER.set(2, || {
+            write("[\"config::defaults\"]\nnumber = 3\n");
+            defaults.reload().unwrap();
+            assert_eq!(DEFAULT_NUMBER.copied(), Some(2));
+        });
+        assert_eq!(DEFAULT_NUMBER.copied(), Some(3));
+        assert_eq!(DEFAULT_NAME.cloned(), None);
+
+        write("[\"config::defaults\"]\nnumber = \"four\"\n");
+        assert!(defaults.reload().is_err());
+        assert_eq!(DEFAULT_NUMBER.copied(), Some(3));
+
+        let wait_for = |number| {
+            for _ in 0..500 {
+                if DEFAULT_NUMBER.copied() == Some(number) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(DEFAULT_NUMBER.copied(), Some(number));
+        };
+        // Changes are noticed even if the file size and modification time stay the same.
+        let watcher = defaults.watch(Duration::from_millis(10), |_| {});
+        write("[\"config::defaults\"]\nnumber = 5\n");
+        wait_for(5);
+        write("[\"config::defaults\"]\nnumber = 6\n");
+        wait_for(6);
+        drop(watcher);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fluid_let! {
+        static FIRST: i32;
+        static SECOND: i32;
+    }
+
+    #[test]
+    fn atomic_reload() {
+        registry::export("config::atomic.first", &FIRST);
+        registry::export("config::atomic.second", &SECOND);
+
+        let path = env::temp_dir().join("fluid-let-config-atomic.json");
+        let write = |n: i32| {
+            let text = format!(
+                "{{\"config::atomic\": {{\"first\": {}, \"second\": {}}}}}",
+                n, n
+            );
+            fs::write(&path, text).unwrap();
+        };
+
+        write(0);
+        let defaults = Defaults::load(&path).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = thread::spawn({
+            let done = Arc::clone(&done);
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    let (first, second) = with_snapshot(|| (FIRST.copied(), SECOND.copied()));
+                    assert_eq!(first, second);
+                }
+            }
+        });
+        for n in 1..100 {
+            write(n);
+            defaults.reload().unwrap();
+            with_snapshot(|| {
+                assert_eq!(FIRST.copied(), Some(n));
+                write(n + 1);
+                defaults.reload().unwrap();
+                assert_eq!(SECOND.copied(), Some(n));
+            });
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+        assert_eq!(FIRST.copied(), Some(100));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fluid_let!(static LAYERED: i32);
+
+    #[test]
+    fn global_defaults() {
+        registry::export("config::layered", &LAYERED);
+
+        let path = env::temp_dir().join("fluid-let-config-layered.toml");
+        fs::write(&path, "\"config::layered\" = 1\n").unwrap();
+
+        LAYERED.set_global_default(2);
+        let defaults = Defaults::load(&path).unwrap();
+        assert_eq!(LAYERED.copied(), Some(2));
+
+        assert_eq!(LAYERED.reset_global_default().as_deref(), Some(&2));
+        assert_eq!(LAYERED.copied(), Some(1));
+
+        LAYERED.set_global_default(3);
+        fs::write(&path, "").unwrap();
+        defaults.reload().unwrap();
+        assert_eq!(LAYERED.copied(), Some(3));
+        LAYERED.reset_global_default();
+        assert_eq!(LAYERED.copied(), None);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
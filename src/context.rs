@@ -0,0 +1,114 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamic variables keyed by type.
+//!
+//! Declaring a dynamic variable requires a named `static`, which is not possible
+//! in generic code. Instead, a value can be _provided_ for the dynamic extent
+//! of a closure, keyed by its type:
+//!
+//! ```
+//! use fluid_let::context;
+//!
+//! struct Logger {
+//!     prefix: &'static str,
+//! }
+//!
+//! fn log(message: &str) {
+//!     context::with(|logger: Option<&Logger>| {
+//!         if let Some(logger) = logger {
+//!             println!("{}{}", logger.prefix, message);
+//!         }
+//!     });
+//! }
+//!
+//! context::provide(&Logger { prefix: "> " }, || {
+//!     log("hello");
+//! });
+//! ```
+//!
+//! Each type behaves like a separate dynamic variable: nested `provide` calls
+//! shadow outer ones and values are restored in LIFO order, even if the closure panics.
+//! Just like dynamic variables, provided values are thread-local.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::DynamicCell;
+
+thread_local! {
+    static CELLS: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Calls the closure with the cell for values of type `T`.
+fn with_cell<T: ?Sized + 'static, R>(f: impl FnOnce(&DynamicCell<T>) -> R) -> R {
+    let cell = CELLS.with(|cells| {
+        let mut cells = cells.borrow_mut();
+        let cell = cells
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(DynamicCell::<T>::empty()));
+        cell.downcast_ref::<DynamicCell<T>>().unwrap() as *const DynamicCell<T>
+    });
+    // This is safe because cells are boxed and never removed from the map,
+    // so they live as long as the current thread.
+    f(unsafe { &*cell })
+}
+
+/// Provides a value of type `T` for the dynamic extent of the closure.
+pub fn provide<T: ?Sized + 'static, R>(value: &T, f: impl FnOnce() -> R) -> R {
+    with_cell(|cell: &DynamicCell<T>| {
+        // This is safe because the guard is dropped right after the closure returns.
+        let _guard_ = unsafe { cell.set(value) };
+        f()
+    })
+}
+
+/// Accesses the current value of type `T`, if any has been provided.
+pub fn with<T: ?Sized + 'static, R>(f: impl FnOnce(Option<&T>) -> R) -> R {
+    // This is safe because the reference cannot outlive this call,
+    // and thus the corresponding provide() call.
+    with_cell(|cell: &DynamicCell<T>| f(unsafe { cell.get() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn provide_and_with() {
+        assert_eq!(with(|value: Option<&i32>| value.copied()), None);
+        provide(&1, || {
+            provide(&"text", || {
+                assert_eq!(with(|value: Option<&i32>| value.copied()), Some(1));
+                assert_eq!(with(|value: Option<&&str>| value.copied()), Some("text"));
+                provide(&2, || {
+                    assert_eq!(with(|value: Option<&i32>| value.copied()), Some(2));
+                });
+                assert_eq!(with(|value: Option<&i32>| value.copied()), Some(1));
+            });
+            thread::spawn(|| {
+                assert_eq!(with(|value: Option<&i32>| value.copied()), None);
+            })
+            .join()
+            .unwrap();
+        });
+        assert_eq!(with(|value: Option<&i32>| value.copied()), None);
+    }
+
+    #[test]
+    fn restore_on_panic() {
+        struct Marker(u8);
+
+        provide(&Marker(1), || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                provide(&Marker(2), || panic!("oops"));
+            }));
+            assert!(result.is_err());
+            assert_eq!(with(|marker: Option<&Marker>| marker.map(|m| m.0)), Some(1));
+        });
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Deadline propagation.
+//!
+//! [`within`] sets a time budget for the dynamic extent of a closure.
+//! Code running in that extent can find out how much time is [`remaining`]
+//! or [`check`] whether the deadline has passed:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use fluid_let::deadline::{self, Expired};
+//!
+//! fn fetch(items: &[u32]) -> Result<Vec<u32>, Expired> {
+//!     let mut fetched = Vec::new();
+//!     for item in items {
+//!         deadline::check()?;
+//!         fetched.push(*item);
+//!     }
+//!     Ok(fetched)
+//! }
+//!
+//! let result = deadline::within(Duration::from_secs(10), || fetch(&[1, 2, 3]));
+//! assert_eq!(result, Ok(vec![1, 2, 3]));
+//!
+//! let result = deadline::within(Duration::from_secs(0), || fetch(&[1, 2, 3]));
+//! assert_eq!(result, Err(Expired));
+//! ```
+//!
+//! Nested calls cannot extend the budget: the effective deadline is the earliest one
+//! of all enclosing calls. Outside of them there is no deadline.
+//!
+//! Time is measured with the clock of [`time`](../time/index.html) module,
+//! so tests can use `time::with_clock()` to make deadlines deterministic.
+//!
+//! [`within`]: fn.within.html
+//! [`remaining`]: fn.remaining.html
+//! [`check`]: fn.check.html
+
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::fluid_let;
+use crate::time;
+
+/// Error returned by [`check`](fn.check.html) when the deadline has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expired;
+
+impl fmt::Display for Expired {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("deadline has expired")
+    }
+}
+
+impl Error for Expired {}
+
+fluid_let!(static DEADLINE: Instant);
+
+/// Sets a deadline `timeout` from now for the dynamic extent of the closure.
+///
+/// If an enclosing deadline is earlier, it stays in effect.
+pub fn within<R>(timeout: Duration, f: impl FnOnce() -> R) -> R {
+    match time::instant().checked_add(timeout) {
+        Some(deadline) => at(deadline, f),
+        None => f(),
+    }
+}
+
+/// Sets a deadline at given instant for the dynamic extent of the closure.
+///
+/// If an enclosing deadline is earlier, it stays in effect.
+pub fn at<R>(deadline: Instant, f: impl FnOnce() -> R) -> R {
+    let deadline = match current() {
+        Some(current) => current.min(deadline),
+        None => deadline,
+    };
+    DEADLINE.set(deadline, f)
+}
+
+/// Returns the effective deadline, if any.
+pub fn current() -> Option<Instant> {
+    DEADLINE.copied()
+}
+
+/// Returns the time left until the deadline, if any.
+///
+/// Returns zero if the deadline has passed.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(time::instant()))
+}
+
+/// Returns `Err(Expired)` if the deadline has passed.
+///
+/// Always succeeds if there is no deadline.
+pub fn check() -> Result<(), Expired> {
+    match remaining() {
+        Some(remaining) if remaining == Duration::ZERO => Err(Expired),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    use crate::time::MockClock;
+
+    #[test]
+    fn nested_deadlines() {
+        let clock = MockClock::new(SystemTime::now());
+        let start = time::with_clock(&clock, time::instant);
+        let advance = |secs| clock.advance(Duration::from_secs(secs));
+        time::with_clock(&clock, || {
+            assert_eq!(remaining(), None);
+            within(Duration::from_secs(10), || {
+                assert_eq!(remaining(), Some(Duration::from_secs(10)));
+                advance(2);
+                within(Duration::from_secs(60), || {
+                    assert_eq!(current(), Some(start + Duration::from_secs(10)));
+                    within(Duration::from_secs(3), || {
+                        assert_eq!(remaining(), Some(Duration::from_secs(3)));
+                        advance(3);
+                        assert_eq!(check(), Err(Expired));
+                    });
+                    assert_eq!(remaining(), Some(Duration::from_secs(5)));
+                    assert_eq!(check(), Ok(()));
+                });
+                advance(10);
+                assert_eq!(remaining(), Some(Duration::ZERO));
+                assert_eq!(check(), Err(Expired));
+            });
+            assert_eq!(check(), Ok(()));
+        });
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Environment variables.
+//!
+//! Dynamic variables can take their default value from the process environment:
+//!
+//! ```
+//! # use fluid_let::fluid_let;
+//! fluid_let!(static HASH_LENGTH: usize = env "APP_HASH_LENGTH" or 32);
+//! ```
+//!
+//! The environment variable is read and parsed with [`FromStr`] when the dynamic variable
+//! is accessed for the first time without any value bound to it. The parsed value is then
+//! shared by all threads. If the environment variable is not set, the default expression
+//! after `or` is used. It can be omitted, then the dynamic variable has no default value
+//! if the environment variable is not set:
+//!
+//! ```
+//! # use fluid_let::fluid_let;
+//! fluid_let!(static LOG_LEVEL: String = env "APP_LOG_LEVEL");
+//! ```
+//!
+//! If the value cannot be parsed, the error is reported to the [error hook](fn.set_hook.html)
+//! and the default value is used instead, as if the environment variable was not set.
+//!
+//! Changing the environment of the process affects all threads. Instead, [`with_vars`]
+//! overlays environment variables for the dynamic extent of a closure in the current thread,
+//! as seen by [`var`]:
+//!
+//! ```
+//! use fluid_let::env;
+//!
+//! env::with_vars(&[("APP_LOG", Some("debug")), ("APP_HOME", None)], || {
+//!     assert_eq!(env::var("APP_LOG").unwrap(), "debug");
+//!     assert!(env::var("APP_HOME").is_err());
+//! });
+//! ```
+//!
+//! Default values of dynamic variables are shared by all threads,
+//! so they are always read from the environment of the process, ignoring overlays.
+//!
+//! [`FromStr`]: https://doc.rust-lang.org/std/str/trait.FromStr.html
+//! [`with_vars`]: fn.with_vars.html
+//! [`var`]: fn.var.html
+
+use std::cell::RefCell;
+use std::env::{self, VarError};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::fluid_let;
+use crate::guard::Guard;
+use crate::shared::Shared;
+
+/// Error in value of an environment variable.
+#[derive(Debug)]
+pub struct ParseError {
+    name: &'static str,
+    value: String,
+    message: String,
+}
+
+impl ParseError {
+    /// Name of the environment variable.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Value of the environment variable.
+    ///
+    /// Values that are not valid Unicode are converted lossily.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid value of {}={:?}: {}",
+            self.name, self.value, self.message
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+type Hook = Box<dyn Fn(&ParseError) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Registers a custom error hook, replacing any that was previously registered.
+///
+/// The error hook is invoked when a dynamic variable fails to parse its default value
+/// from the environment. The default hook prints a warning to standard error.
+pub fn set_hook(hook: Box<dyn Fn(&ParseError) + Send + Sync>) {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(hook);
+}
+
+/// Unregisters the current error hook, returning it.
+///
+/// If no custom hook is registered, the default hook will be returned.
+pub fn take_hook() -> Box<dyn Fn(&ParseError) + Send + Sync> {
+    HOOK.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_else(|| Box::new(default_hook))
+}
+
+fn default_hook(error: &ParseError) {
+    eprintln!("fluid-let: ignoring {}", error);
+}
+
+fn report(error: ParseError) {
+    match &*HOOK.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(hook) => hook(&error),
+        None => default_hook(&error),
+    }
+}
+
+static GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+/// Reads the environment again.
+///
+/// Default values of dynamic variables are read from the environment only once.
+/// After this call they will be read again on the next access. This is mostly useful
+/// for tests which modify the environment.
+pub fn reload_defaults() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Environment variables overlaid by `with_vars`, innermost first.
+#[derive(Clone, Copy)]
+struct Overlay<'a> {
+    vars: &'a [(&'a str, Option<&'a str>)],
+    parent: Option<&'a Overlay<'a>>,
+}
+
+fluid_let!(static OVERLAY: for<'a> Overlay<'a>);
+
+/// Overlays environment variables for the dynamic extent of the closure.
+///
+/// Variables with `Some` value are set, variables with `None` are removed.
+/// If a name is repeated, the last value is used.
+pub fn with_vars<R>(vars: &[(&str, Option<&str>)], f: impl FnOnce() -> R) -> R {
+    OVERLAY.get(|parent| {
+        let overlay = Overlay { vars, parent };
+        OVERLAY.set(overlay, f)
+    })
+}
+
+/// Returns the value of an environment variable.
+///
+/// The innermost overlay which has this variable takes precedence
+/// over the environment of the process.
+pub fn var(name: &str) -> Result<String, VarError> {
+    let overlaid = OVERLAY.get(|overlay| {
+        let mut overlay = overlay;
+        while let Some(current) = overlay {
+            let found = current.vars.iter().rev().find(|(n, _)| *n == name);
+            if let Some((_, value)) = found {
+                return Some(value.map(str::to_owned));
+            }
+            overlay = current.parent;
+        }
+        None
+    });
+    match overlaid {
+        Some(Some(value)) => Ok(value),
+        Some(None) => Err(VarError::NotPresent),
+        None => env::var(name),
+    }
+}
+
+/// Default value of a dynamic variable, read from the environment.
+#[doc(hidden)]
+pub struct EnvDefault<T: ?Sized> {
+    name: &'static str,
+    parse: fn(&str) -> Result<Box<T>, String>,
+    default: fn() -> Option<Box<T>>,
+    generation: AtomicUsize,
+    reload: Mutex<()>,
+    value: Shared<T>,
+}
+
+impl<T: ?Sized> EnvDefault<T> {
+    /// Initialize an environment default.
+    ///
+    /// Use [`fluid_let!`](../macro.fluid_let.html) macro to do this.
+    pub const fn new(
+        name: &'static str,
+        parse: fn(&str) -> Result<Box<T>, String>,
+        default: fn() -> Option<Box<T>>,
+    ) -> Self {
+        EnvDefault {
+            name,
+            parse,
+            default,
+            generation: AtomicUsize::new(0),
+            reload: Mutex::new(()),
+            value: Shared::empty(),
+        }
+    }
+
+    /// Current default value, reading the environment if necessary.
+    ///
+    /// Values read from the environment are checked with the guard, if any.
+    pub(crate) fn get(&self, guard: Option<Guard<T>>) -> Option<Arc<T>> {
+        let current = GENERATION.load(Ordering::SeqCst);
+        if self.generation.load(Ordering::Acquire) != current && !Loading::active(self) {
+            // The guard and the error hook may access this variable. Recursive reads
+            // during loading return the previous value instead of loading it again.
+            let _loading_ = Loading::enter(self);
+            let parsed = {
+                let _lock = self.reload.lock().unwrap_or_else(|e| e.into_inner());
+                if self.generation.load(Ordering::Acquire) == current {
+                    return self.value.load();
+                }
+                self.parse()
+            };
+            let (value, error) = match parsed.and_then(|value| self.check(value, guard)) {
+                Ok(value) => (value, None),
+                Err(error) => ((self.default)(), Some(error)),
+            };
+            let stored = {
+                let _lock = self.reload.lock().unwrap_or_else(|e| e.into_inner());
+                let stale = self.generation.load(Ordering::Acquire) != current;
+                if stale {
+                    self.value.store(value.map(Arc::from));
+                    self.generation.store(current, Ordering::Release);
+                }
+                stale
+            };
+            if let (true, Some(error)) = (stored, error) {
+                report(error);
+            }
+        }
+        self.value.load()
+    }
+
+    /// Reads and parses the environment variable.
+    ///
+    /// Returns the default value if the variable is not set.
+    fn parse(&self) -> Result<Parsed<T>, ParseError> {
+        let value = match env::var(self.name) {
+            Ok(value) => value,
+            Err(VarError::NotPresent) => return Ok(Parsed::Default((self.default)())),
+            Err(VarError::NotUnicode(value)) => {
+                return Err(ParseError {
+                    name: self.name,
+                    value: value.to_string_lossy().into_owned(),
+                    message: "not valid Unicode".to_owned(),
+                })
+            }
+        };
+        match (self.parse)(&value) {
+            Ok(parsed) => Ok(Parsed::Value(value, parsed)),
+            Err(message) => Err(ParseError {
+                name: self.name,
+                value,
+                message,
+            }),
+        }
+    }
+
+    /// Checks the parsed value with the guard.
+    fn check(
+        &self,
+        parsed: Parsed<T>,
+        guard: Option<Guard<T>>,
+    ) -> Result<Option<Box<T>>, ParseError> {
+        let (value, parsed) = match parsed {
+            Parsed::Value(value, parsed) => (value, parsed),
+            Parsed::Default(default) => return Ok(default),
+        };
+        match guard.map(|guard| guard(&parsed)) {
+            None | Some(Ok(None)) => Ok(Some(parsed)),
+            Some(Ok(Some(converted))) => Ok(Some(converted)),
+            Some(Err(e)) => Err(ParseError {
+                name: self.name,
+                value,
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Value read from the environment.
+enum Parsed<T: ?Sized> {
+    /// The variable is set to this value, parsed.
+    Value(String, Box<T>),
+    /// The variable is not set, this is the default value.
+    Default(Option<Box<T>>),
+}
+
+thread_local! {
+    /// Addresses of environment defaults which are being loaded by the current thread.
+    static LOADING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks an environment default as being loaded by the current thread until dropped.
+struct Loading {
+    address: usize,
+}
+
+impl Loading {
+    fn address<T: ?Sized>(env: &EnvDefault<T>) -> usize {
+        env as *const EnvDefault<T> as *const () as usize
+    }
+
+    fn active<T: ?Sized>(env: &EnvDefault<T>) -> bool {
+        let address = Loading::address(env);
+        // Do not reload while the thread is exiting, the previous value will do.
+        LOADING
+            .try_with(|loading| loading.borrow().contains(&address))
+            .unwrap_or(true)
+    }
+
+    fn enter<T: ?Sized>(env: &EnvDefault<T>) -> Loading {
+        let address = Loading::address(env);
+        LOADING.with(|loading| loading.borrow_mut().push(address));
+        Loading { address }
+    }
+}
+
+impl Drop for Loading {
+    fn drop(&mut self) {
+        LOADING.with(|loading| loading.borrow_mut().retain(|&a| a != self.address));
+    }
+}
+
+/// Parses a value with `FromStr`.
+#[doc(hidden)]
+pub fn parse<T>(value: &str) -> Result<Box<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match value.parse::<T>() {
+        Ok(value) => Ok(Box::new(value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, AtomicI32};
+
+    use crate::fluid_let;
+
+    // Tests in this module modify the environment and the error hook.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn defaults() {
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        fluid_let! {
+            static NUMBER: i32 = env "FLUID_LET_TEST_DEFAULTS" or 42;
+            static STRING: String = env "FLUID_LET_TEST_DEFAULTS";
+        }
+
+        env::remove_var("FLUID_LET_TEST_DEFAULTS");
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(42));
+        assert_eq!(STRING.cloned(), None);
+
+        env::set_var("FLUID_LET_TEST_DEFAULTS", "9000");
+        assert_eq!(NUMBER.copied(), Some(42));
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(9000));
+        assert_eq!(STRING.cloned(), Some("9000".to_owned()));
+
+        NUMBER.set(1, || assert_eq!(NUMBER.copied(), Some(1)));
+
+        env::remove_var("FLUID_LET_TEST_DEFAULTS");
+        reload_defaults();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        fluid_let!(static NUMBER: i32 = env "FLUID_LET_TEST_PARSE_ERRORS" or 42);
+
+        static REPORTED: AtomicBool = AtomicBool::new(false);
+        set_hook(Box::new(|error| {
+            assert_eq!(error.name(), "FLUID_LET_TEST_PARSE_ERRORS");
+            assert_eq!(error.value(), "forty-two");
+            REPORTED.store(true, Ordering::SeqCst);
+        }));
+
+        env::set_var("FLUID_LET_TEST_PARSE_ERRORS", "forty-two");
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(42));
+        assert!(REPORTED.load(Ordering::SeqCst));
+
+        drop(take_hook());
+        env::remove_var("FLUID_LET_TEST_PARSE_ERRORS");
+        reload_defaults();
+    }
+
+    #[test]
+    fn reentrant_reads() {
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        fn small(value: &i32) -> Result<Option<i32>, crate::InvalidValue> {
+            // Recursive reads return the previous value.
+            assert_eq!(NUMBER.copied(), Some(1));
+            match *value < 10 {
+                true => Ok(None),
+                false => Err(crate::InvalidValue::new("too large")),
+            }
+        }
+
+        fluid_let! {
+            static NUMBER: i32 = env "FLUID_LET_TEST_REENTRANT_READS" or 1; guard = small;
+        }
+
+        // The hook is called after the new value is stored.
+        static SEEN: AtomicI32 = AtomicI32::new(0);
+        set_hook(Box::new(|_| {
+            SEEN.store(NUMBER.copied().unwrap(), Ordering::SeqCst);
+        }));
+
+        env::remove_var("FLUID_LET_TEST_REENTRANT_READS");
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(1));
+
+        env::set_var("FLUID_LET_TEST_REENTRANT_READS", "5");
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(5));
+
+        env::set_var("FLUID_LET_TEST_REENTRANT_READS", "not a number");
+        reload_defaults();
+        assert_eq!(NUMBER.copied(), Some(1));
+        assert_eq!(SEEN.load(Ordering::SeqCst), 1);
+
+        drop(take_hook());
+        env::remove_var("FLUID_LET_TEST_REENTRANT_READS");
+        reload_defaults();
+    }
+
+    #[test]
+    fn overlays() {
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        env::set_var("FLUID_LET_TEST_OVERLAYS", "process");
+        with_vars(&[("FLUID_LET_TEST_OVERLAYS", Some("outer"))], || {
+            assert_eq!(var("FLUID_LET_TEST_OVERLAYS").unwrap(), "outer");
+            let inner = [
+                ("FLUID_LET_TEST_OVERLAYS", Some("ignored")),
+                ("FLUID_LET_TEST_OVERLAYS", None),
+            ];
+            with_vars(&inner, || {
+                assert_eq!(var("FLUID_LET_TEST_OVERLAYS"), Err(VarError::NotPresent));
+                assert_eq!(env::var("FLUID_LET_TEST_OVERLAYS").unwrap(), "process");
+            });
+            with_vars(&[("FLUID_LET_TEST_OTHER", Some("other"))], || {
+                assert_eq!(var("FLUID_LET_TEST_OVERLAYS").unwrap(), "outer");
+            });
+        });
+        assert_eq!(var("FLUID_LET_TEST_OVERLAYS").unwrap(), "process");
+        env::remove_var("FLUID_LET_TEST_OVERLAYS");
+    }
+}
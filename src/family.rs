@@ -0,0 +1,209 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamic variables with borrowed values.
+
+use std::borrow::Borrow;
+use std::mem;
+use std::thread::LocalKey;
+
+use crate::{DynamicCell, DynamicCellGuard};
+
+/// A family of types parameterized by a lifetime.
+///
+/// Implemented by [`fluid_let!`](macro.fluid_let.html) for variables declared
+/// with `for<'a>` types. You should not need to implement it manually.
+///
+/// # Safety
+///
+/// `Member<'a>` must be covariant in `'a`, that is, `Member<'long>` must be
+/// a subtype of `Member<'short>`. [`shorten`](#tymethod.shorten) must return its argument.
+pub unsafe trait Family: 'static {
+    /// Type with a particular lifetime.
+    type Member<'a>: ?Sized;
+
+    /// Shortens the lifetime of the value.
+    ///
+    /// Implement this as `{ value }`, it will compile only for covariant types.
+    fn shorten<'long: 'short, 'short>(
+        value: &'short Self::Member<'long>,
+    ) -> &'short Self::Member<'short>;
+}
+
+/// A global dynamic variable with borrowed values.
+///
+/// Dynamic variables declared by [`fluid_let!`](macro.fluid_let.html) with `for<'a>` types
+/// hold values which may borrow from the caller, such as `&'a str` or `Config<'a>`:
+///
+/// ```
+/// use fluid_let::fluid_let;
+///
+/// struct Context<'a> {
+///     name: &'a str,
+/// }
+///
+/// fluid_let!(static CONTEXT: for<'a> Context<'a>);
+///
+/// fn greet() -> String {
+///     CONTEXT.get(|context| match context {
+///         Some(context) => format!("Hello, {}!", context.name),
+///         None => "Hello!".to_owned(),
+///     })
+/// }
+///
+/// let name = String::from("world");
+/// CONTEXT.set(Context { name: &name }, || {
+///     assert_eq!(greet(), "Hello, world!");
+/// });
+/// ```
+///
+/// The value passed to the [`get`](#method.get) closure has its lifetime shortened
+/// to the closure call, so borrows cannot escape it:
+///
+/// ```compile_fail
+/// # use fluid_let::fluid_let;
+/// # struct Context<'a> {
+/// #     name: &'a str,
+/// # }
+/// # fluid_let!(static CONTEXT: for<'a> Context<'a>);
+/// let name = CONTEXT.get(|context| context.map(|context| context.name));
+/// ```
+///
+/// ```compile_fail
+/// # use fluid_let::fluid_let;
+/// # fluid_let!(static NAME: for<'a> &'a str);
+/// let mut name = "";
+/// NAME.get(|current| {
+///     if let Some(current) = current {
+///         name = current;
+///     }
+/// });
+/// ```
+///
+/// Types must be covariant in their lifetime. Types with interior mutability
+/// of borrowed data are rejected since their values could be replaced with
+/// shorter-lived borrows:
+///
+/// ```compile_fail
+/// # use std::cell::Cell;
+/// # use fluid_let::fluid_let;
+/// fluid_let!(static NAME: for<'a> Cell<&'a str>);
+/// ```
+///
+/// Variables with borrowed values have no initial values, global defaults,
+/// or per-thread values.
+pub struct DynamicFamily<F: Family> {
+    cell: &'static LocalKey<DynamicCell<F::Member<'static>>>,
+}
+
+impl<F: Family> DynamicFamily<F> {
+    /// Initialize a dynamic variable.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn new(cell: &'static LocalKey<DynamicCell<F::Member<'static>>>) -> Self {
+        Self { cell }
+    }
+
+    /// Access current value of the dynamic variable.
+    pub fn get<R>(&self, f: impl for<'r> FnOnce(Option<&'r F::Member<'r>>) -> R) -> R {
+        self.cell.with(|current| {
+            // This is safe because the lifetime of the reference returned by get()
+            // is limited to this block so it cannot outlive any value set by set()
+            // in the caller frames. Since the member type is covariant, the value
+            // can be safely viewed with this shorter lifetime.
+            f(unsafe { current.get() }.map(F::shorten))
+        })
+    }
+
+    /// Bind a new value to the dynamic variable.
+    pub fn set<'a, R>(&self, value: impl Borrow<F::Member<'a>>, f: impl FnOnce() -> R) -> R {
+        // This is safe because the guard returned by set_guard() is guaranteed to be
+        // dropped after the thunk returns and before anything else executes.
+        let _guard_ = unsafe { self.set_guard(value.borrow()) };
+        f()
+    }
+
+    /// Bind a new value to the dynamic variable.
+    ///
+    /// # Safety
+    ///
+    /// The value is bound for the lifetime of the returned guard. The guard must be
+    /// dropped before the end of lifetime of the new and old assignment values.
+    /// If the variable is assigned another value while this guard is alive, it must
+    /// not be dropped until that new assignment is undone.
+    #[doc(hidden)]
+    pub unsafe fn set_guard<'a>(
+        &self,
+        value: &F::Member<'a>,
+    ) -> DynamicCellGuard<'static, F::Member<'static>> {
+        // We use transmute to pretend that the value and the cell live forever.
+        // This is really the case while assignments are properly scoped.
+        let value: &F::Member<'static> = mem::transmute(value);
+        let current: &'static DynamicCell<F::Member<'static>> =
+            self.cell.with(|current| mem::transmute(current));
+        current.set(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fluid_let, fluid_set};
+
+    struct Context<'a> {
+        name: &'a str,
+        parent: Option<&'a Context<'a>>,
+    }
+
+    impl<'a> Context<'a> {
+        fn path(&self) -> String {
+            match self.parent {
+                Some(parent) => format!("{}/{}", parent.path(), self.name),
+                None => self.name.to_owned(),
+            }
+        }
+    }
+
+    fluid_let! {
+        static CONTEXT: for<'a> Context<'a>;
+        static NAME: for<'a> &'a str;
+    }
+
+    fn current_path() -> Option<String> {
+        CONTEXT.get(|context| context.map(|context| context.path()))
+    }
+
+    #[test]
+    fn borrowed_values() {
+        assert_eq!(current_path(), None);
+
+        let root = String::from("root");
+        let root = Context {
+            name: &root,
+            parent: None,
+        };
+        CONTEXT.set(&root, || {
+            assert_eq!(current_path(), Some("root".to_owned()));
+            let child = String::from("child");
+            CONTEXT.set(
+                Context {
+                    name: &child,
+                    parent: Some(&root),
+                },
+                || assert_eq!(current_path(), Some("root/child".to_owned())),
+            );
+            assert_eq!(current_path(), Some("root".to_owned()));
+        });
+        assert_eq!(current_path(), None);
+    }
+
+    #[test]
+    fn scoped_assignment() {
+        let name = String::from("name");
+        {
+            fluid_set!(NAME, &name[..]);
+            NAME.get(|current| assert_eq!(current, Some(&"name")));
+        }
+        NAME.get(|current| assert_eq!(current, None));
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamically scoped working directory and filesystem.
+//!
+//! [`std::env::set_current_dir`] changes the working directory of the whole process,
+//! affecting all threads. Instead, [`with_current_dir`] binds a directory for the dynamic
+//! extent of a closure in the current thread only. Functions of this module [`resolve`]
+//! relative paths against it:
+//!
+//! ```no_run
+//! use fluid_let::fs;
+//!
+//! fs::with_current_dir("/etc", || {
+//!     let hosts = fs::read_to_string("hosts").unwrap();
+//!     assert_eq!(hosts, std::fs::read_to_string("/etc/hosts").unwrap());
+//! })
+//! .unwrap();
+//! ```
+//!
+//! Relative paths given to `with_current_dir` are resolved against the enclosing
+//! bound directory, or against the working directory of the process at the time
+//! of the call. Outside of `with_current_dir` calls, paths are left as is,
+//! so they are resolved against the working directory of the process.
+//!
+//! The filesystem itself can be replaced too. [`read`], [`write`], [`metadata`], and
+//! [`read_dir`] dispatch through the [`Fs`] implementation bound with [`with_fs`],
+//! which is [`OsFs`] by default. Tests can bind an in-memory [`MemFs`] instead:
+//!
+//! ```
+//! use fluid_let::fs::{self, MemFs};
+//!
+//! fn save_config(text: &str) -> std::io::Result<()> {
+//!     fs::write("config/app.toml", text)
+//! }
+//!
+//! let memory = MemFs::new();
+//! fs::with_fs(&memory, || {
+//!     fs::with_current_dir("/home/user", || {
+//!         save_config("answer = 42").unwrap();
+//!     })
+//!     .unwrap();
+//!     let text = fs::read_to_string("/home/user/config/app.toml").unwrap();
+//!     assert_eq!(text, "answer = 42");
+//! });
+//! ```
+//!
+//! Note that [`open`] and [`create`] always use the OS filesystem
+//! as they return `std::fs::File`.
+//!
+//! [`std::env::set_current_dir`]: https://doc.rust-lang.org/std/env/fn.set_current_dir.html
+//! [`with_current_dir`]: fn.with_current_dir.html
+//! [`resolve`]: fn.resolve.html
+//! [`read`]: fn.read.html
+//! [`write`]: fn.write.html
+//! [`metadata`]: fn.metadata.html
+//! [`read_dir`]: fn.read_dir.html
+//! [`with_fs`]: fn.with_fs.html
+//! [`open`]: fn.open.html
+//! [`create`]: fn.create.html
+//! [`Fs`]: trait.Fs.html
+//! [`OsFs`]: struct.OsFs.html
+//! [`MemFs`]: struct.MemFs.html
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::fluid_let;
+
+fluid_let! {
+    static CURRENT_DIR: Path;
+    static CURRENT_FS: for<'a> &'a dyn Fs;
+}
+
+/// Filesystem operations.
+///
+/// Paths passed to these methods are already resolved with [`resolve`](fn.resolve.html).
+pub trait Fs {
+    /// Reads the entire contents of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Writes the entire contents of a file, replacing it if it exists.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Queries metadata of a file or a directory.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Lists paths of entries in a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Metadata of a file or a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    is_dir: bool,
+    len: u64,
+}
+
+impl Metadata {
+    /// Makes metadata of a file with given size.
+    pub fn file(len: u64) -> Self {
+        Metadata { is_dir: false, len }
+    }
+
+    /// Makes metadata of a directory.
+    pub fn dir() -> Self {
+        Metadata {
+            is_dir: true,
+            len: 0,
+        }
+    }
+
+    /// Returns true if this is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Returns true if this is a file.
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Filesystem of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFs;
+
+impl Fs for OsFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(if metadata.is_dir() {
+            Metadata::dir()
+        } else {
+            Metadata::file(metadata.len())
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+}
+
+/// In-memory filesystem.
+///
+/// Directories are created implicitly when files are written into them.
+/// Entries of directories are listed in sorted order.
+#[derive(Debug, Default)]
+pub struct MemFs {
+    entries: Mutex<BTreeMap<PathBuf, Entry>>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    File(Vec<u8>),
+    Dir,
+}
+
+impl MemFs {
+    /// Makes a new empty filesystem.
+    pub fn new() -> Self {
+        MemFs::default()
+    }
+}
+
+/// Normalizes path for use as a key, removing "." components and trailing slashes,
+/// and resolving ".." lexically. The root has no parent.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+fn is_a_directory(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is a directory", path.display()),
+    )
+}
+
+fn not_a_directory(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is not a directory", path.display()),
+    )
+}
+
+impl Fs for MemFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&normalize(path))
+        {
+            Some(Entry::File(contents)) => Ok(contents.clone()),
+            Some(Entry::Dir) => Err(is_a_directory(path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = normalize(path);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(Entry::Dir) = entries.get(&path) {
+            return Err(is_a_directory(&path));
+        }
+        for dir in path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            match entries.get(dir) {
+                Some(Entry::File(_)) => return Err(not_a_directory(dir)),
+                Some(Entry::Dir) => break,
+                None => {}
+            }
+        }
+        for dir in path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() || entries.contains_key(dir) {
+                break;
+            }
+            entries.insert(dir.to_path_buf(), Entry::Dir);
+        }
+        entries.insert(path, Entry::File(contents.to_vec()));
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&normalize(path))
+        {
+            Some(Entry::File(contents)) => Ok(Metadata::file(contents.len() as u64)),
+            Some(Entry::Dir) => Ok(Metadata::dir()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let path = normalize(path);
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(&path) {
+            Some(Entry::Dir) => Ok(entries
+                .keys()
+                .filter(|entry| entry.parent() == Some(&path))
+                .cloned()
+                .collect()),
+            Some(Entry::File(_)) => Err(not_a_directory(&path)),
+            None => Err(not_found(&path)),
+        }
+    }
+}
+
+/// Binds the filesystem for the dynamic extent of the closure.
+pub fn with_fs<R>(fs: &dyn Fs, f: impl FnOnce() -> R) -> R {
+    CURRENT_FS.set(fs, f)
+}
+
+/// Calls the closure with the current filesystem.
+fn current_fs<R>(f: impl FnOnce(&dyn Fs) -> R) -> R {
+    CURRENT_FS.get(|fs| match fs {
+        Some(fs) => f(*fs),
+        None => f(&OsFs),
+    })
+}
+
+/// Binds the working directory for the dynamic extent of the closure.
+///
+/// Relative paths are resolved against the current working directory. If no directory
+/// is bound, that is the working directory of the process, unless a filesystem is bound
+/// with [`with_fs`](fn.with_fs.html): then relative paths are bound as is.
+/// The directory is not required to exist.
+///
+/// Returns an error if the working directory of the process cannot be determined.
+pub fn with_current_dir<R>(path: impl AsRef<Path>, f: impl FnOnce() -> R) -> io::Result<R> {
+    let path = resolve(path);
+    // Rooted paths are treated as absolute, even if they lack a drive on Windows.
+    let path = if path.has_root() || CURRENT_FS.get(|fs| fs.is_some()) {
+        path
+    } else {
+        env::current_dir()?.join(path)
+    };
+    Ok(CURRENT_DIR.set(path, f))
+}
+
+/// Returns the current working directory.
+///
+/// That is the innermost bound directory, or the working directory of the process.
+pub fn current_dir() -> io::Result<PathBuf> {
+    match CURRENT_DIR.get(|dir| dir.map(Path::to_path_buf)) {
+        Some(dir) => Ok(dir),
+        None => env::current_dir(),
+    }
+}
+
+/// Resolves relative path against the innermost bound directory.
+///
+/// Absolute paths, and all paths outside of `with_current_dir` calls, are returned as is.
+pub fn resolve(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    CURRENT_DIR.get(|dir| match dir {
+        Some(dir) => dir.join(path),
+        None => path.to_path_buf(),
+    })
+}
+
+/// Opens a file in read-only mode, like `File::open`.
+pub fn open(path: impl AsRef<Path>) -> io::Result<File> {
+    File::open(resolve(path))
+}
+
+/// Opens a file in write-only mode, like `File::create`.
+pub fn create(path: impl AsRef<Path>) -> io::Result<File> {
+    File::create(resolve(path))
+}
+
+/// Reads the entire contents of a file, like `fs::read`.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    current_fs(|fs| fs.read(&resolve(path)))
+}
+
+/// Reads the entire contents of a file into a string, like `fs::read_to_string`.
+pub fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes the entire contents of a file, like `fs::write`.
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    current_fs(|fs| fs.write(&resolve(path), contents.as_ref()))
+}
+
+/// Queries metadata of a file or a directory, like `fs::metadata`.
+pub fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    current_fs(|fs| fs.metadata(&resolve(path)))
+}
+
+/// Lists paths of entries in a directory, like `fs::read_dir`.
+pub fn read_dir(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    current_fs(|fs| fs.read_dir(&resolve(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn relative_paths() {
+        let root = env::temp_dir().join(format!("fluid-let-fs-{}", std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+
+        with_current_dir(&root, || {
+            assert_eq!(current_dir().unwrap(), root);
+            create("file").unwrap().write_all(b"outer").unwrap();
+            with_current_dir("nested", || {
+                assert_eq!(resolve("file"), root.join("nested/file"));
+                assert_eq!(resolve(&root), root);
+                create("file").unwrap().write_all(b"inner").unwrap();
+                assert_eq!(read_to_string("../file").unwrap(), "outer");
+                thread::spawn(|| assert_eq!(resolve("file"), Path::new("file")))
+                    .join()
+                    .unwrap();
+            })
+            .unwrap();
+            assert_eq!(read_to_string("nested/file").unwrap(), "inner");
+        })
+        .unwrap();
+        assert_eq!(resolve("file"), Path::new("file"));
+
+        let absolute = env::current_dir().unwrap().join("relative");
+        with_current_dir("relative", || {
+            assert_eq!(current_dir().unwrap(), absolute);
+            assert_eq!(resolve("file"), absolute.join("file"));
+        })
+        .unwrap();
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn memory_fs() {
+        let memory = MemFs::new();
+        with_fs(&memory, || {
+            write("/a/b/file", "text").unwrap();
+            with_current_dir("/a", || write("c", [0xFF, 0, 1]))
+                .unwrap()
+                .unwrap();
+            assert_eq!(read("/a/c").unwrap(), [0xFF, 0, 1]);
+            assert_eq!(read_to_string("/a/./b/file").unwrap(), "text");
+            assert!(read_to_string("/a/c").is_err());
+            assert_eq!(metadata("/a/c").unwrap(), Metadata::file(3));
+            assert_eq!(metadata("/a/b/").unwrap(), Metadata::dir());
+            assert_eq!(
+                read_dir("/a").unwrap(),
+                [Path::new("/a/b"), Path::new("/a/c")]
+            );
+            assert_eq!(read_dir("/").unwrap(), [Path::new("/a")]);
+            assert_eq!(
+                read("/missing").unwrap_err().kind(),
+                io::ErrorKind::NotFound
+            );
+            assert!(read("/a").is_err());
+            assert!(write("/a", "").is_err());
+            assert!(write("/a/c/d", "").is_err());
+
+            with_current_dir("/a/b", || {
+                assert_eq!(read("../c").unwrap(), [0xFF, 0, 1]);
+                assert_eq!(read_to_string("../../a/b/./file").unwrap(), "text");
+                assert_eq!(read_dir("../..").unwrap(), [Path::new("/a")]);
+                write("../../../d", "root").unwrap();
+            })
+            .unwrap();
+            assert_eq!(read_to_string("/d").unwrap(), "root");
+
+            // Working directory of the process is not used with in-memory filesystem.
+            with_current_dir("a", || {
+                assert_eq!(current_dir().unwrap(), Path::new("a"));
+                write("e", "relative").unwrap();
+            })
+            .unwrap();
+            assert_eq!(read_to_string("a/e").unwrap(), "relative");
+        });
+        assert!(metadata("/a/b/file").is_err());
+    }
+}
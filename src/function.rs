@@ -0,0 +1,199 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamically overridable functions.
+
+/// Declares functions which can be overridden for a dynamic extent.
+///
+/// # Examples
+///
+/// The function is declared with its default implementation:
+///
+/// ```
+/// use fluid_let::fluid_fn;
+///
+/// fluid_fn! {
+///     /// Computes a hash of the data.
+///     pub fn hash(data: &[u8]) -> u64 {
+///         data.iter().fold(0, |hash, &byte| hash * 31 + byte as u64)
+///     }
+/// }
+///
+/// assert_eq!(hash(b"abc"), 96354);
+/// ```
+///
+/// Along with the function, a module with the same name is declared.
+/// Its `override_with` function replaces the implementation of the function
+/// for the dynamic extent of the closure:
+///
+/// ```
+/// # use fluid_let::fluid_fn;
+/// # fluid_fn! {
+/// #     pub fn hash(data: &[u8]) -> u64 {
+/// #         data.iter().fold(0, |hash, &byte| hash * 31 + byte as u64)
+/// #     }
+/// # }
+/// #
+/// hash::override_with(
+///     |_data, _next| 42,
+///     || {
+///         assert_eq!(hash(b"abc"), 42);
+///     },
+/// );
+/// ```
+///
+/// The override receives the next implementation, which it may call.
+/// That is either the previous override or the default implementation:
+///
+/// ```
+/// # use fluid_let::fluid_fn;
+/// # fluid_fn! {
+/// #     pub fn hash(data: &[u8]) -> u64 {
+/// #         data.iter().fold(0, |hash, &byte| hash * 31 + byte as u64)
+/// #     }
+/// # }
+/// #
+/// hash::override_with(
+///     |data, next| next(data) + 1,
+///     || {
+///         assert_eq!(hash(b"abc"), 96355);
+///     },
+/// );
+/// ```
+///
+/// Just like values of dynamic variables, overrides are thread-local.
+///
+/// Multiple functions can be declared at once.
+///
+/// Generic functions and methods are not supported.
+/// Arguments must be plain identifiers, not patterns.
+#[macro_export]
+macro_rules! fluid_fn {
+    // Function without return type.
+    {
+        $(#[$attr:meta])*
+        $pub:vis fn $name:ident($($arg:ident: $type:ty),* $(,)?) $body:block
+        $($rest:tt)*
+    } => {
+        $crate::fluid_fn! {
+            $(#[$attr])*
+            $pub fn $name($($arg: $type),*) -> () $body
+            $($rest)*
+        }
+    };
+    // Function with return type.
+    {
+        $(#[$attr:meta])*
+        $pub:vis fn $name:ident($($arg:ident: $type:ty),* $(,)?) -> $ret:ty $body:block
+        $($rest:tt)*
+    } => {
+        $(#[$attr])*
+        $pub fn $name($($arg: $type),*) -> $ret {
+            fn default($($arg: $type),*) -> $ret $body
+            $name::call(&default, $($arg),*)
+        }
+
+        /// Overrides of the function with the same name.
+        $pub mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[derive(Clone, Copy)]
+            struct Overrides<'a> {
+                frame: Option<&'a Frame<'a>>,
+            }
+
+            struct Frame<'a> {
+                function: &'a (dyn Fn($($type,)* &dyn Fn($($type),*) -> $ret) -> $ret + 'a),
+                next: Overrides<'a>,
+            }
+
+            $crate::fluid_let!(static OVERRIDES: for<'a> Overrides<'a>);
+
+            /// Overrides the function for the dynamic extent of `body`.
+            ///
+            /// The override receives the function arguments and the next implementation.
+            pub fn override_with<R>(
+                function: impl Fn($($type,)* &dyn Fn($($type),*) -> $ret) -> $ret,
+                body: impl FnOnce() -> R,
+            ) -> R {
+                OVERRIDES.get(|overrides| {
+                    let frame = Frame {
+                        function: &function,
+                        next: overrides.copied().unwrap_or(Overrides { frame: None }),
+                    };
+                    OVERRIDES.set(Overrides { frame: Some(&frame) }, body)
+                })
+            }
+
+            pub(super) fn call(default: &dyn Fn($($type),*) -> $ret, $($arg: $type),*) -> $ret {
+                OVERRIDES.get(|overrides| {
+                    let overrides = overrides.copied().unwrap_or(Overrides { frame: None });
+                    invoke(overrides, default, $($arg),*)
+                })
+            }
+
+            fn invoke(
+                overrides: Overrides<'_>,
+                default: &dyn Fn($($type),*) -> $ret,
+                $($arg: $type),*
+            ) -> $ret {
+                match overrides.frame {
+                    Some(frame) => (frame.function)(
+                        $($arg,)*
+                        &|$($arg),*| invoke(frame.next, default, $($arg),*),
+                    ),
+                    None => default($($arg),*),
+                }
+            }
+        }
+
+        $crate::fluid_fn!($($rest)*);
+    };
+    // No definitions (recursion base).
+    {} => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    thread_local! {
+        static CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fluid_fn! {
+        fn length(text: &str, extra: usize) -> usize {
+            text.len() + extra
+        }
+
+        fn record() {
+            CALLS.with(|calls| calls.set(calls.get() + 1));
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        assert_eq!(length("abc", 1), 4);
+        length::override_with(
+            |text, extra, next| next(text, extra) * 10,
+            || {
+                assert_eq!(length("abc", 1), 40);
+                length::override_with(
+                    |text, _, next| next(text, 0) + 1,
+                    || assert_eq!(length("abc", 1), 31),
+                );
+                assert_eq!(length("abc", 2), 50);
+            },
+        );
+        assert_eq!(length("abc", 1), 4);
+    }
+
+    #[test]
+    fn without_return_type() {
+        record();
+        record::override_with(|_| {}, record);
+        record::override_with(|next| next(), record);
+        assert_eq!(CALLS.with(Cell::get), 2);
+    }
+}
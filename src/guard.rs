@@ -0,0 +1,98 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Validation of dynamic variable values and rebinding policies.
+
+use std::error::Error;
+use std::fmt;
+
+/// Validates and converts new values of a dynamic variable.
+///
+/// Returns `Ok(None)` to accept the value as is, or `Ok(Some(value))` to replace it.
+#[doc(hidden)]
+pub type Guard<T> = fn(&T) -> Result<Option<Box<T>>, InvalidValue>;
+
+/// Error returned by guards of dynamic variables for rejected values.
+///
+/// See [`fluid_let!`](macro.fluid_let.html) for details on guards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidValue {
+    message: String,
+}
+
+impl InvalidValue {
+    /// Makes a new error with given description.
+    pub fn new(message: impl Into<String>) -> Self {
+        InvalidValue {
+            message: message.into(),
+        }
+    }
+
+    /// Description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for InvalidValue {}
+
+/// Whether a dynamic variable can be rebound while it has a value bound.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shadow {
+    Allow,
+    Deny,
+}
+
+/// Shadowing policies accepted by [`fluid_let!`](macro.fluid_let.html).
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub mod shadow {
+    use super::Shadow;
+
+    pub const allow: Shadow = Shadow::Allow;
+    pub const deny: Shadow = Shadow::Deny;
+}
+
+/// Error in binding a value to a dynamic variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SetError {
+    /// The value has been rejected by the guard.
+    Invalid(InvalidValue),
+    /// The variable already has a value bound and does not allow shadowing it.
+    Shadowing,
+    /// The current value has been bound with `set_final`.
+    Final,
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetError::Invalid(e) => write!(f, "invalid value of dynamic variable: {}", e),
+            SetError::Shadowing => write!(f, "dynamic variable does not allow shadowing"),
+            SetError::Final => write!(f, "dynamic variable has a final value bound"),
+        }
+    }
+}
+
+impl Error for SetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SetError::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<InvalidValue> for SetError {
+    fn from(e: InvalidValue) -> Self {
+        SetError::Invalid(e)
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamically bound input and output ports.
+//!
+//! Instead of writing to the standard output directly, code can write to the
+//! [`current_output`] port, which can be rebound for a dynamic extent.
+//! This is convenient for capturing output in tests:
+//!
+//! ```
+//! use fluid_let::{fprintln, io};
+//!
+//! fn greet(name: &str) {
+//!     fprintln!("Hello, {}!", name);
+//! }
+//!
+//! let output = io::with_output_to_string(|| greet("world"));
+//! assert_eq!(output, "Hello, world!\n");
+//! ```
+//!
+//! Outside of [`with_output`], [`with_error`], and [`with_input`] calls the ports are
+//! the standard output, standard error, and standard input of the process.
+//!
+//! Just like dynamic variables, ports are bound per thread.
+//!
+//! [`current_output`]: fn.current_output.html
+//! [`with_output`]: fn.with_output.html
+//! [`with_error`]: fn.with_error.html
+//! [`with_input`]: fn.with_input.html
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::fluid_let;
+
+// Ports lend their streams to callbacks.
+type OutputPort<'a> = &'a dyn Fn(&mut dyn FnMut(&mut dyn Write));
+type InputPort<'a> = &'a dyn Fn(&mut dyn FnMut(&mut dyn BufRead));
+
+fluid_let! {
+    static OUTPUT: for<'a> OutputPort<'a>;
+    static ERROR: for<'a> OutputPort<'a>;
+    static INPUT: for<'a> InputPort<'a>;
+}
+
+/// Calls the closure with the stream of the output port.
+fn lend_output<R>(port: OutputPort, f: impl FnOnce(&mut dyn Write) -> R) -> R {
+    let mut f = Some(f);
+    let mut result = None;
+    port(&mut |stream| result = f.take().map(|f| f(stream)));
+    result.expect("port must lend its stream")
+}
+
+/// Calls the closure with the stream of the input port.
+fn lend_input<R>(port: InputPort, f: impl FnOnce(&mut dyn BufRead) -> R) -> R {
+    let mut f = Some(f);
+    let mut result = None;
+    port(&mut |stream| result = f.take().map(|f| f(stream)));
+    result.expect("port must lend its stream")
+}
+
+/// Binds the output port for the dynamic extent of the closure.
+pub fn with_output<R>(output: &mut dyn Write, f: impl FnOnce() -> R) -> R {
+    let output = RefCell::new(output);
+    let port: OutputPort = &|f| f(&mut **output.borrow_mut());
+    OUTPUT.set(port, f)
+}
+
+/// Binds the error port for the dynamic extent of the closure.
+pub fn with_error<R>(error: &mut dyn Write, f: impl FnOnce() -> R) -> R {
+    let error = RefCell::new(error);
+    let port: OutputPort = &|f| f(&mut **error.borrow_mut());
+    ERROR.set(port, f)
+}
+
+/// Binds the input port for the dynamic extent of the closure.
+pub fn with_input<R>(input: &mut dyn BufRead, f: impl FnOnce() -> R) -> R {
+    let input = RefCell::new(input);
+    let port: InputPort = &|f| f(&mut **input.borrow_mut());
+    INPUT.set(port, f)
+}
+
+/// Calls the closure with the current output port.
+///
+/// # Panics
+///
+/// If the port is bound with `with_output` and is already in use by an outer call.
+pub fn current_output<R>(f: impl FnOnce(&mut dyn Write) -> R) -> R {
+    OUTPUT.get(|port| match port {
+        Some(port) => lend_output(*port, f),
+        None => f(&mut io::stdout().lock()),
+    })
+}
+
+/// Calls the closure with the current error port.
+///
+/// # Panics
+///
+/// If the port is bound with `with_error` and is already in use by an outer call.
+pub fn current_error<R>(f: impl FnOnce(&mut dyn Write) -> R) -> R {
+    ERROR.get(|port| match port {
+        Some(port) => lend_output(*port, f),
+        None => f(&mut io::stderr().lock()),
+    })
+}
+
+/// Calls the closure with the current input port.
+///
+/// # Panics
+///
+/// If the port is bound with `with_input` and is already in use by an outer call.
+pub fn current_input<R>(f: impl FnOnce(&mut dyn BufRead) -> R) -> R {
+    INPUT.get(|port| match port {
+        Some(port) => lend_input(*port, f),
+        None => f(&mut io::stdin().lock()),
+    })
+}
+
+/// Returns everything written to the output port by the closure.
+///
+/// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+pub fn with_output_to_string(f: impl FnOnce()) -> String {
+    let mut output = Vec::new();
+    with_output(&mut output, f);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Reads the input port from the string for the dynamic extent of the closure.
+pub fn with_input_from_string<R>(input: &str, f: impl FnOnce() -> R) -> R {
+    with_input(&mut input.as_bytes(), f)
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    if let Err(e) = current_output(|output| output.write_fmt(args)) {
+        panic!("failed printing to current output: {}", e);
+    }
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    if let Err(e) = current_error(|error| error.write_fmt(args)) {
+        panic!("failed printing to current error: {}", e);
+    }
+}
+
+/// Prints to the [current output port](io/fn.current_output.html).
+///
+/// Works like `print!`.
+#[macro_export]
+macro_rules! fprint {
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the [current output port](io/fn.current_output.html), with a newline.
+///
+/// Works like `println!`.
+#[macro_export]
+macro_rules! fprintln {
+    () => {
+        $crate::fprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Prints to the [current error port](io/fn.current_error.html).
+///
+/// Works like `eprint!`.
+#[macro_export]
+macro_rules! feprint {
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the [current error port](io/fn.current_error.html), with a newline.
+///
+/// Works like `eprintln!`.
+#[macro_export]
+macro_rules! feprintln {
+    () => {
+        $crate::feprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_capture() {
+        let mut error = Vec::new();
+        let output = with_output_to_string(|| {
+            fprint!("a");
+            let inner = with_output_to_string(|| fprintln!("b{}", 1));
+            assert_eq!(inner, "b1\n");
+            with_error(&mut error, || {
+                feprint!("error");
+                feprintln!();
+            });
+            fprintln!();
+        });
+        assert_eq!(output, "a\n");
+        assert_eq!(error, b"error\n");
+    }
+
+    #[test]
+    fn input() {
+        with_input_from_string("first\nsecond\n", || {
+            let mut line = String::new();
+            current_input(|input| input.read_line(&mut line)).unwrap();
+            assert_eq!(line, "first\n");
+            let rest = current_input(|input| input.lines().collect::<Result<Vec<_>, _>>());
+            assert_eq!(rest.unwrap(), ["second"]);
+        });
+    }
+}
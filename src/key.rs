@@ -0,0 +1,277 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Dynamic variables created at runtime.
+
+use std::any::Any;
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::{DynamicCell, DynamicVariable};
+
+/// A dynamic variable created at runtime.
+///
+/// Dynamic variables declared by [`fluid_let!`](macro.fluid_let.html) must be `static`.
+/// `DynamicKey` provides the same interface as [`DynamicVariable`](struct.DynamicVariable.html)
+/// for variables created dynamically, e.g., one per plugin instance:
+///
+/// ```
+/// use fluid_let::DynamicKey;
+///
+/// struct Plugin {
+///     verbose: DynamicKey<bool>,
+/// }
+///
+/// let plugin = Plugin {
+///     verbose: DynamicKey::new(),
+/// };
+///
+/// plugin.verbose.set(true, || {
+///     assert_eq!(plugin.verbose.copied(), Some(true));
+/// });
+/// assert_eq!(plugin.verbose.copied(), None);
+/// ```
+///
+/// Each key is a distinct variable. Just like static dynamic variables, values are
+/// thread-local. Keys are backed by a per-thread slot table, and their slots are
+/// reused once keys are dropped. Dropping a key frees its slot in the current thread,
+/// other threads free their slots the next time they access any key.
+pub struct DynamicKey<T: ?Sized + 'static> {
+    index: usize,
+    id: u64,
+    phantom: PhantomData<fn(&T) -> &T>,
+}
+
+struct Slot {
+    id: u64,
+    cell: Box<dyn Any>,
+}
+
+struct Allocator {
+    free: Vec<usize>,
+    // Ids of live keys, by slot index.
+    owners: Vec<Option<u64>>,
+}
+
+static ALLOCATOR: Mutex<Allocator> = Mutex::new(Allocator {
+    free: Vec::new(),
+    owners: Vec::new(),
+});
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Number of dropped keys, so that threads know when to look for stale slots.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static SLOTS: RefCell<Vec<Option<Slot>>> = const { RefCell::new(Vec::new()) };
+    static SEEN_DROPPED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Frees slots of the current thread which belong to dropped keys.
+fn free_stale_slots(slots: &mut [Option<Slot>]) {
+    let dropped = DROPPED.load(Ordering::Acquire);
+    if SEEN_DROPPED.with(Cell::get) == dropped {
+        return;
+    }
+    let allocator = ALLOCATOR.lock().unwrap_or_else(|e| e.into_inner());
+    for (slot, owner) in slots.iter_mut().zip(&allocator.owners) {
+        if slot.as_ref().map(|slot| slot.id) != *owner {
+            *slot = None;
+        }
+    }
+    SEEN_DROPPED.with(|seen| seen.set(dropped));
+}
+
+impl<T: ?Sized> DynamicVariable<T> {
+    /// Creates a new dynamic variable at runtime.
+    ///
+    /// This is the same as [`DynamicKey::new`](struct.DynamicKey.html#method.new).
+    pub fn new_key() -> DynamicKey<T> {
+        DynamicKey::new()
+    }
+}
+
+impl<T: ?Sized> DynamicKey<T> {
+    /// Creates a new dynamic variable.
+    pub fn new() -> Self {
+        let mut allocator = ALLOCATOR.lock().unwrap_or_else(|e| e.into_inner());
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let index = match allocator.free.pop() {
+            Some(index) => index,
+            None => {
+                allocator.owners.push(None);
+                allocator.owners.len() - 1
+            }
+        };
+        allocator.owners[index] = Some(id);
+        Self {
+            index,
+            id,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Calls the closure with the cell of this variable in the current thread.
+    fn with_cell<R>(&self, f: impl FnOnce(&DynamicCell<T>) -> R) -> R {
+        let cell = SLOTS.with(|slots| {
+            let mut slots = slots.borrow_mut();
+            free_stale_slots(&mut slots);
+            if slots.len() <= self.index {
+                slots.resize_with(self.index + 1, || None);
+            }
+            let slot = &mut slots[self.index];
+            // Slot might have been used by a dropped key. Since the key is dropped,
+            // it has no values bound, so the old cell can be safely replaced.
+            if slot.as_ref().map(|slot| slot.id) != Some(self.id) {
+                *slot = Some(Slot {
+                    id: self.id,
+                    cell: Box::new(DynamicCell::<T>::empty()),
+                });
+            }
+            let cell = &slot.as_ref().unwrap().cell;
+            cell.downcast_ref::<DynamicCell<T>>().unwrap() as *const DynamicCell<T>
+        });
+        // This is safe because cells are boxed and are not replaced while the key is alive,
+        // so they live as long as the current thread or this key.
+        f(unsafe { &*cell })
+    }
+
+    /// Access current value of the dynamic variable.
+    pub fn get<R>(&self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        // This is safe because the lifetime of the reference returned by get()
+        // is limited to this block so it cannot outlive any value set by set()
+        // in the caller frames.
+        self.with_cell(|cell| f(unsafe { cell.get() }))
+    }
+
+    /// Bind a new value to the dynamic variable.
+    pub fn set<R>(&self, value: impl Borrow<T>, f: impl FnOnce() -> R) -> R {
+        self.with_cell(|cell| {
+            // This is safe because the guard returned by set() is guaranteed to be
+            // dropped after the thunk returns and before anything else executes.
+            let _guard_ = unsafe { cell.set(value.borrow()) };
+            f()
+        })
+    }
+}
+
+impl<T: Clone> DynamicKey<T> {
+    /// Clone current value of the dynamic variable.
+    pub fn cloned(&self) -> Option<T> {
+        self.get(|value| value.cloned())
+    }
+}
+
+impl<T: Copy> DynamicKey<T> {
+    /// Copy current value of the dynamic variable.
+    pub fn copied(&self) -> Option<T> {
+        self.get(|value| value.copied())
+    }
+}
+
+impl<T: ?Sized> Default for DynamicKey<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> Drop for DynamicKey<T> {
+    fn drop(&mut self) {
+        // The key is being dropped, so it has no values bound and the cell can be freed.
+        // Thread-local storage might be already destroyed if the key is dropped late.
+        let cell = SLOTS.try_with(|slots| {
+            let mut slots = slots.borrow_mut();
+            match slots.get_mut(self.index) {
+                Some(slot) if slot.as_ref().map(|slot| slot.id) == Some(self.id) => slot.take(),
+                _ => None,
+            }
+        });
+        drop(cell);
+        let mut allocator = ALLOCATOR.lock().unwrap_or_else(|e| e.into_inner());
+        allocator.owners[self.index] = None;
+        allocator.free.push(self.index);
+        DROPPED.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    #[test]
+    fn dynamic_scoping() {
+        let a = DynamicKey::<i32>::new();
+        let b = DynamicKey::<i32>::new();
+
+        assert_eq!(a.copied(), None);
+        a.set(1, || {
+            b.set(2, || {
+                assert_eq!(a.copied(), Some(1));
+                assert_eq!(b.copied(), Some(2));
+                a.set(3, || assert_eq!(a.cloned(), Some(3)));
+                assert_eq!(a.copied(), Some(1));
+            });
+            let t = thread::spawn(|| assert_eq!(DynamicKey::<i32>::new().copied(), None));
+            t.join().unwrap();
+        });
+        assert_eq!(a.copied(), None);
+
+        let s = DynamicKey::<str>::new();
+        s.set("text", || s.get(|value| assert_eq!(value, Some("text"))));
+    }
+
+    #[test]
+    fn slot_reuse() {
+        let live_slots = || SLOTS.with(|slots| slots.borrow().iter().flatten().count());
+
+        let a = Arc::new(DynamicVariable::<i32>::new_key());
+        let c = Arc::new(DynamicKey::<i32>::new());
+        a.set(1, || assert_eq!(a.copied(), Some(1)));
+        assert_eq!(live_slots(), 1);
+
+        let (used, used_rx) = mpsc::channel::<()>();
+        let (dropped, dropped_rx) = mpsc::channel::<Arc<DynamicKey<String>>>();
+        let t = thread::spawn({
+            let a = Arc::clone(&a);
+            move || {
+                a.set(2, || assert_eq!(a.copied(), Some(2)));
+                c.set(3, || assert_eq!(c.copied(), Some(3)));
+                assert_eq!(live_slots(), 2);
+                drop(a);
+                used.send(()).unwrap();
+
+                // Cell of "a" is freed when this thread accesses another key.
+                let b = dropped_rx.recv().unwrap();
+                assert_eq!(c.copied(), None);
+                assert_eq!(live_slots(), 1);
+
+                assert_eq!(b.cloned(), None);
+                b.set(String::from("other"), || {
+                    assert_eq!(b.cloned(), Some(String::from("other")));
+                });
+            }
+        });
+        used_rx.recv().unwrap();
+        drop(a);
+        assert_eq!(live_slots(), 0);
+
+        // New keys may take the slot of "a" and must start empty.
+        let b = Arc::new(DynamicKey::<String>::new());
+        assert_eq!(b.cloned(), None);
+        b.set(String::from("text"), || {
+            assert_eq!(b.cloned(), Some(String::from("text")));
+        });
+        dropped.send(Arc::clone(&b)).unwrap();
+        t.join().unwrap();
+        assert_eq!(b.cloned(), None);
+        drop(b);
+        assert_eq!(live_slots(), 0);
+    }
+}
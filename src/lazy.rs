@@ -0,0 +1,127 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Lazily computed values.
+
+use std::cell::{Cell, OnceCell};
+
+/// A value which is computed on first access.
+pub(crate) trait Force<T: ?Sized> {
+    /// Returns the value, computing it if necessary.
+    ///
+    /// # Panics
+    ///
+    /// If the value is accessed while it is being computed,
+    /// or if the computation has panicked.
+    fn force(&self) -> &T;
+
+    /// Returns the value if it has been computed.
+    fn computed(&self) -> Option<&T>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    Running,
+    Panicked,
+}
+
+/// A value computed by a closure at most once.
+pub(crate) struct Lazy<T, F> {
+    init: Cell<Option<F>>,
+    state: Cell<State>,
+    value: OnceCell<T>,
+    on_init: Option<fn(&T)>,
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Makes a new lazy value, calling `on_init` hook after it is computed.
+    pub(crate) fn new(init: F, on_init: Option<fn(&T)>) -> Self {
+        Lazy {
+            init: Cell::new(Some(init)),
+            state: Cell::new(State::Pending),
+            value: OnceCell::new(),
+            on_init,
+        }
+    }
+}
+
+/// Marks computation as panicked unless forgotten.
+struct PanicGuard<'a> {
+    state: &'a Cell<State>,
+}
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        self.state.set(State::Panicked);
+    }
+}
+
+impl<T, F: FnOnce() -> T> Force<T> for Lazy<T, F> {
+    fn force(&self) -> &T {
+        if let Some(value) = self.value.get() {
+            return value;
+        }
+        match self.state.get() {
+            State::Pending => {}
+            State::Running => {
+                panic!("dynamic variable is accessed while its lazy value is being computed")
+            }
+            State::Panicked => panic!("lazy value of dynamic variable has failed to compute"),
+        }
+        let init = self.init.take().expect("lazy value must be pending");
+        self.state.set(State::Running);
+        let guard = PanicGuard { state: &self.state };
+        let value = init();
+        std::mem::forget(guard);
+        self.state.set(State::Pending);
+        // Recursive calls panic, so the value cannot be already initialized here.
+        let value = self.value.get_or_init(|| value);
+        if let Some(on_init) = self.on_init {
+            on_init(value);
+        }
+        value
+    }
+
+    fn computed(&self) -> Option<&T> {
+        self.value.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn computed_once() {
+        let calls = Cell::new(0);
+        let lazy = Lazy::new(
+            || {
+                calls.set(calls.get() + 1);
+                42
+            },
+            None,
+        );
+        assert_eq!(lazy.computed(), None);
+        assert_eq!(calls.get(), 0);
+        assert_eq!(*lazy.force(), 42);
+        assert_eq!(*lazy.force(), 42);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(lazy.computed(), Some(&42));
+    }
+
+    #[test]
+    fn panicked_computation() {
+        let lazy = Lazy::new(|| -> i32 { panic!("oops") }, None);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *lazy.force()));
+        assert!(result.is_err());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *lazy.force()));
+        let message = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(
+            message,
+            "lazy value of dynamic variable has failed to compute"
+        );
+    }
+}
@@ -39,6 +39,15 @@
 //!
 //! Here `LOG_LEVEL` has `Some(&LogLevel::Info)` as its default value.
 //!
+//! Default values can also be read from [environment variables](env/index.html)
+//! when the dynamic variable is accessed for the first time:
+//!
+//! ```
+//! # use fluid_let::fluid_let;
+//! #
+//! fluid_let!(static HASH_LENGTH: usize = env "APP_HASH_LENGTH" or 32);
+//! ```
+//!
 //! # Setting dynamic variables
 //!
 //! [`set`] is used to give value to a dynamic variable:
@@ -67,6 +76,51 @@
 //! You can’t directly modify the dynamic variable value after setting it,
 //! but you can use something like `Cell` or `RefCell` to circumvent that.
 //!
+//! Since only references are stored, dynamic variables can have unsized types
+//! such as `str`, slices, or trait objects:
+//!
+//! ```
+//! # use std::cell::RefCell;
+//! # use std::io::Write;
+//! #
+//! # use fluid_let::fluid_let;
+//! #
+//! fluid_let! {
+//!     static PREFIX: str;
+//!     static OUTPUT: RefCell<dyn Write>;
+//! }
+//!
+//! let prefix = String::from("> ");
+//! let buffer = RefCell::new(Vec::new());
+//!
+//! PREFIX.set(&prefix[..], || {
+//!     OUTPUT.set(&buffer as &RefCell<dyn Write>, || {
+//!         // ...
+//!     });
+//! });
+//! ```
+//!
+//! Static initialization and per-thread values require sized types.
+//!
+//! Values can also borrow from the caller if the variable type is declared with a lifetime,
+//! see [`DynamicFamily`](struct.DynamicFamily.html):
+//!
+//! ```
+//! # use fluid_let::fluid_let;
+//! #
+//! struct Config<'a> {
+//!     name: &'a str,
+//! }
+//!
+//! fluid_let!(static CONFIG: for<'a> Config<'a>);
+//!
+//! let name = String::from("example");
+//!
+//! CONFIG.set(Config { name: &name }, || {
+//!     CONFIG.get(|config| assert_eq!(config.unwrap().name, "example"));
+//! });
+//! ```
+//!
 //! The new value is in effect within the _dynamic extent_ of the assignment,
 //! that is within the closure passed to `set`. Once the closure returns, the
 //! previous value of the variable is restored.
@@ -207,10 +261,42 @@
 //! In this case you will probably need some synchronization to use the shared
 //! object in a safe manner, just like you would do when using `Arc` and friends.
 //!
+//! Since every thread starts without any values bound, it might be convenient to give
+//! dynamic variables a process-wide default value with [`set_global_default`]:
+//!
+//! [`set_global_default`]: struct.DynamicVariable.html#method.set_global_default
+//!
+//! ```
+//! # use std::thread;
+//! #
+//! # use fluid_let::fluid_let;
+//! #
+//! fluid_let!(static HASH_LENGTH: usize);
+//!
+//! HASH_LENGTH.set_global_default(16);
+//!
+//! thread::spawn(|| {
+//!     assert_eq!(HASH_LENGTH.copied(), Some(16));
+//!
+//!     HASH_LENGTH.set(32, || {
+//!         assert_eq!(HASH_LENGTH.copied(), Some(32));
+//!     });
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+//!
+//! Reading the default value does not take any locks, but it is slower than reading
+//! a value bound in the current thread.
+//!
+//! If a thread needs its own value for its whole life, such as a worker thread in a pool,
+//! it can be bound once with [`set_for_thread`] instead of wrapping the thread body in `set`.
+//!
+//! [`set_for_thread`]: struct.DynamicVariable.html#method.set_for_thread
+//!
 //! # Features
 //!
-//! Currently, there is only one optional feature: `"static-init"`,
-//! gating static initialization of dynamic variables:
+//! Static initialization of dynamic variables is gated by `"static-init"` feature:
 //!
 //! ```
 //! # use fluid_let::fluid_let;
@@ -224,12 +310,54 @@
 //!
 //! The API for accessing known-initialized variables has not stabilized yet
 //! and may be subject to changes.
+//!
+//! `"config"` feature enables loading dynamic variables from [configuration files](config/index.html).
+//! It pulls in `serde`, `serde_json`, and `toml` as dependencies.
 
+use std::any::Any;
 use std::borrow::Borrow;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::HashMap;
 use std::mem;
+use std::sync::Arc;
 use std::thread::LocalKey;
 
+pub mod cancel;
+pub mod cli;
+pub mod condition;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod context;
+pub mod deadline;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod registry;
+pub mod time;
+
+mod alloc;
+mod family;
+mod function;
+mod guard;
+mod key;
+mod lazy;
+mod reader;
+mod shared;
+mod wind;
+
+pub use crate::alloc::FluidAlloc;
+pub use crate::family::{DynamicFamily, Family};
+#[doc(hidden)]
+pub use crate::guard::{shadow, Guard, Shadow};
+pub use crate::guard::{InvalidValue, SetError};
+pub use crate::key::DynamicKey;
+pub use crate::reader::Reader;
+pub use crate::wind::wind;
+
+use crate::env::EnvDefault;
+use crate::lazy::{Force, Lazy};
+use crate::shared::Shared;
+
 #[cfg(feature = "static-init")]
 /// Declares global dynamic variables.
 ///
@@ -250,6 +378,71 @@ use std::thread::LocalKey;
 /// fluid_let!(static ENABLED: bool = true);
 /// ```
 ///
+/// Default value can be read from an environment variable, parsed with `FromStr`:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// fluid_let!(static VERBOSE: bool = env "APP_VERBOSE" or false);
+/// ```
+///
+/// See [`env`](env/index.html) module for details.
+///
+/// Values may borrow from the caller if the type is declared with a lifetime:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// fluid_let!(static PREFIX: for<'a> &'a str);
+/// ```
+///
+/// See [`DynamicFamily`](struct.DynamicFamily.html) for details.
+///
+/// New values can be validated and converted by a guard function:
+///
+/// ```
+/// # use fluid_let::{fluid_let, InvalidValue};
+/// fn validate_port(port: &u16) -> Result<Option<u16>, InvalidValue> {
+///     match *port {
+///         0 => Ok(Some(8080)),
+///         1..=1023 => Err(InvalidValue::new("privileged port")),
+///         _ => Ok(None),
+///     }
+/// }
+///
+/// fluid_let!(static PORT: u16 = env "APP_PORT" or 8080; guard = validate_port);
+/// ```
+///
+/// The guard returns `Ok(None)` to accept the value as is, `Ok(Some(value))` to replace it,
+/// or an error to reject it. Values are checked whenever they are bound to the variable:
+/// [`set`](struct.DynamicVariable.html#method.set) panics on invalid values while
+/// [`try_set`](struct.DynamicVariable.html#method.try_set) returns an error.
+/// Values from the environment are checked as well, but initial values are not.
+///
+/// Variables can forbid rebinding them while they have a value bound:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// # struct SecurityContext;
+/// fluid_let!(static SECURITY_CONTEXT: SecurityContext; shadow = deny);
+/// ```
+///
+/// Then nested [`set`](struct.DynamicVariable.html#method.set) calls fail.
+/// The default policy is `shadow = allow`.
+///
+/// Hooks can be called when values are bound and unbound, like [`wind`](fn.wind.html):
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// # struct Lock;
+/// # impl Lock { fn acquire(&self) {} fn release(&self) {} }
+/// fluid_let!(static LOCK: Lock; on_enter = Lock::acquire; on_exit = Lock::release);
+/// ```
+///
+/// The exit hook is called when the binding is undone, even if the thread is panicking.
+/// Lazily computed values are passed to hooks only if they have been computed.
+///
+/// Options can be combined, but must be given in this order:
+/// `; guard = validate; shadow = deny; on_enter = enter; on_exit = exit`.
+///
 /// Multiple declarations with attributes and visibility modifiers are also supported:
 ///
 /// ```
@@ -267,33 +460,154 @@ use std::thread::LocalKey;
 /// See also [crate-level documentation](index.html) for usage examples.
 #[macro_export]
 macro_rules! fluid_let {
+    // Simple case: a single definition with borrowed values.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: for<$lt:lifetime> $type:ty
+    } => {
+        $(#[$attr])*
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        $pub struct $name {}
+
+        $(#[$attr])*
+        unsafe impl $crate::Family for $name {
+            type Member<$lt> = $type;
+
+            fn shorten<'long: 'short, 'short>(
+                value: &'short Self::Member<'long>,
+            ) -> &'short Self::Member<'short> {
+                value
+            }
+        }
+
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicFamily<$name> = {
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<<$name as $crate::Family>::Member<'static>> = $crate::DynamicCell::empty();
+            }
+            $crate::DynamicFamily::new(&VARIABLE)
+        };
+    };
+    // Multiple definitions (iteration), with borrowed values.
+    // This must be matched before other rules as "for" cannot start a type there.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: for<$lt:lifetime> $type:ty;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: for<$lt> $type);
+        $crate::fluid_let!($($rest)*);
+    };
     // Simple case: a single definition with None value.
     {
         $(#[$attr:meta])*
-        $pub:vis static $name:ident: $type:ty
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
+    } => {
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicVariable<$type> = {
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
+            }
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::new(&VARIABLE),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
+        };
+    };
+    // Simple case: a single definition with default value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
+    } => {
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicVariable<$type> = {
+            fn default() -> Option<Box<$type>> {
+                Some(Box::new($default))
+            }
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
+            }
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::with_env(&VARIABLE, $crate::env::EnvDefault::new($var, $crate::env::parse::<$type>, default)),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
+        };
+    };
+    // Simple case: a single definition with optional value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
     } => {
         $(#[$attr])*
         $pub static $name: $crate::DynamicVariable<$type> = {
+            fn default() -> Option<Box<$type>> {
+                None
+            }
             thread_local! {
                 static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
             }
-            $crate::DynamicVariable::new(&VARIABLE)
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::with_env(&VARIABLE, $crate::env::EnvDefault::new($var, $crate::env::parse::<$type>, default)),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
         };
     };
     // Simple case: a single definition with Some value.
     {
         $(#[$attr:meta])*
-        $pub:vis static $name:ident: $type:ty = $value:expr
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
     } => {
         $(#[$attr])*
         $pub static $name: $crate::DynamicVariable<$type> = {
             static DEFAULT: $type = $value;
             thread_local! {
-                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::with_static(&DEFAULT);
+                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
             }
-            $crate::DynamicVariable::new(&VARIABLE)
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::with_static(&VARIABLE, &DEFAULT),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
         };
     };
+    // Multiple definitions (iteration), with None value and options up to on_exit.
+    // Since options are optional, each last option needs a separate rule to tell
+    // it apart from the following definitions.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
     // Multiple definitions (iteration), with None value.
     {
         $(#[$attr:meta])*
@@ -303,6 +617,132 @@ macro_rules! fluid_let {
         $crate::fluid_let!($(#[$attr])* $pub static $name: $type);
         $crate::fluid_let!($($rest)*);
     };
+    // Multiple definitions (iteration), with default value from the environment and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
     // Multiple definitions (iteration), with Some value.
     {
         $(#[$attr:meta])*
@@ -333,43 +773,226 @@ macro_rules! fluid_let {
 /// fluid_let!(static ENABLED: bool);
 /// ```
 ///
-/// Multiple declarations with attributes and visibility modifiers are also supported:
+/// Default value can be read from an environment variable, parsed with `FromStr`:
 ///
 /// ```
 /// # use fluid_let::fluid_let;
-/// fluid_let! {
-///     /// Length of `Debug` representation of hashes in characters.
-///     pub static HASH_LENGTH: usize;
+/// fluid_let!(static VERBOSE: bool = env "APP_VERBOSE" or false);
+/// ```
 ///
-///     /// If set to true then passwords will be printed to logs.
-///     #[cfg(test)]
-///     static DUMP_PASSWORDS: bool;
+/// See [`env`](env/index.html) module for details.
+///
+/// Values may borrow from the caller if the type is declared with a lifetime:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// fluid_let!(static PREFIX: for<'a> &'a str);
+/// ```
+///
+/// See [`DynamicFamily`](struct.DynamicFamily.html) for details.
+///
+/// New values can be validated and converted by a guard function:
+///
+/// ```
+/// # use fluid_let::{fluid_let, InvalidValue};
+/// fn validate_port(port: &u16) -> Result<Option<u16>, InvalidValue> {
+///     match *port {
+///         0 => Ok(Some(8080)),
+///         1..=1023 => Err(InvalidValue::new("privileged port")),
+///         _ => Ok(None),
+///     }
 /// }
+///
+/// fluid_let!(static PORT: u16 = env "APP_PORT" or 8080; guard = validate_port);
 /// ```
 ///
-/// See also [crate-level documentation](index.html) for usage examples.
-#[macro_export]
-macro_rules! fluid_let {
+/// The guard returns `Ok(None)` to accept the value as is, `Ok(Some(value))` to replace it,
+/// or an error to reject it. Values are checked whenever they are bound to the variable:
+/// [`set`](struct.DynamicVariable.html#method.set) panics on invalid values while
+/// [`try_set`](struct.DynamicVariable.html#method.try_set) returns an error.
+/// Values from the environment are checked as well, but initial values are not.
+///
+/// Variables can forbid rebinding them while they have a value bound:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// # struct SecurityContext;
+/// fluid_let!(static SECURITY_CONTEXT: SecurityContext; shadow = deny);
+/// ```
+///
+/// Then nested [`set`](struct.DynamicVariable.html#method.set) calls fail.
+/// The default policy is `shadow = allow`.
+///
+/// Hooks can be called when values are bound and unbound, like [`wind`](fn.wind.html):
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// # struct Lock;
+/// # impl Lock { fn acquire(&self) {} fn release(&self) {} }
+/// fluid_let!(static LOCK: Lock; on_enter = Lock::acquire; on_exit = Lock::release);
+/// ```
+///
+/// The exit hook is called when the binding is undone, even if the thread is panicking.
+/// Lazily computed values are passed to hooks only if they have been computed.
+///
+/// Options can be combined, but must be given in this order:
+/// `; guard = validate; shadow = deny; on_enter = enter; on_exit = exit`.
+///
+/// Multiple declarations with attributes and visibility modifiers are also supported:
+///
+/// ```
+/// # use fluid_let::fluid_let;
+/// fluid_let! {
+///     /// Length of `Debug` representation of hashes in characters.
+///     pub static HASH_LENGTH: usize;
+///
+///     /// If set to true then passwords will be printed to logs.
+///     #[cfg(test)]
+///     static DUMP_PASSWORDS: bool;
+/// }
+/// ```
+///
+/// See also [crate-level documentation](index.html) for usage examples.
+#[macro_export]
+macro_rules! fluid_let {
+    // Simple case: a single definition with borrowed values.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: for<$lt:lifetime> $type:ty
+    } => {
+        $(#[$attr])*
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        $pub struct $name {}
+
+        $(#[$attr])*
+        unsafe impl $crate::Family for $name {
+            type Member<$lt> = $type;
+
+            fn shorten<'long: 'short, 'short>(
+                value: &'short Self::Member<'long>,
+            ) -> &'short Self::Member<'short> {
+                value
+            }
+        }
+
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicFamily<$name> = {
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<<$name as $crate::Family>::Member<'static>> = $crate::DynamicCell::empty();
+            }
+            $crate::DynamicFamily::new(&VARIABLE)
+        };
+    };
+    // Multiple definitions (iteration), with borrowed values.
+    // This must be matched before other rules as "for" cannot start a type there.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: for<$lt:lifetime> $type:ty;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: for<$lt> $type);
+        $crate::fluid_let!($($rest)*);
+    };
     // Simple case: a single definition with None value.
     {
         $(#[$attr:meta])*
-        $pub:vis static $name:ident: $type:ty
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
+    } => {
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicVariable<$type> = {
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
+            }
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::new(&VARIABLE),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
+        };
+    };
+    // Simple case: a single definition with default value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
+    } => {
+        $(#[$attr])*
+        $pub static $name: $crate::DynamicVariable<$type> = {
+            fn default() -> Option<Box<$type>> {
+                Some(Box::new($default))
+            }
+            thread_local! {
+                static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
+            }
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::with_env(&VARIABLE, $crate::env::EnvDefault::new($var, $crate::env::parse::<$type>, default)),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
+        };
+    };
+    // Simple case: a single definition with optional value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
     } => {
         $(#[$attr])*
         $pub static $name: $crate::DynamicVariable<$type> = {
+            fn default() -> Option<Box<$type>> {
+                None
+            }
             thread_local! {
                 static VARIABLE: $crate::DynamicCell<$type> = $crate::DynamicCell::empty();
             }
-            $crate::DynamicVariable::new(&VARIABLE)
+            $crate::__fluid_let_options!(
+                $crate::DynamicVariable::with_env(&VARIABLE, $crate::env::EnvDefault::new($var, $crate::env::parse::<$type>, default)),
+                $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)? $(; on_exit = $on_exit)?
+            )
         };
     };
     // Simple case: a single definition with Some value.
     {
         $(#[$attr:meta])*
-        $pub:vis static $name:ident: $type:ty = $value:expr
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?
     } => {
         compile_error!("Static initialization is unstable, use \"static-init\" feature to opt-in");
     };
+    // Multiple definitions (iteration), with None value and options up to on_exit.
+    // Since options are optional, each last option needs a separate rule to tell
+    // it apart from the following definitions.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with None value and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
     // Multiple definitions (iteration), with None value.
     {
         $(#[$attr:meta])*
@@ -379,6 +1002,132 @@ macro_rules! fluid_let {
         $crate::fluid_let!($(#[$attr])* $pub static $name: $type);
         $crate::fluid_let!($($rest)*);
     };
+    // Multiple definitions (iteration), with default value from the environment and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with default value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal or $default:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var or $default);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with optional value from the environment.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = env $var:literal;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = env $var);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to on_exit.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)?; on_exit = $on_exit:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)? $(; shadow = $shadow)? $(; on_enter = $on_enter)?; on_exit = $on_exit);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to on_enter.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)? $(; shadow = $shadow:ident)?; on_enter = $on_enter:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)? $(; shadow = $shadow)?; on_enter = $on_enter);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to shadow.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr $(; guard = $guard:expr)?; shadow = $shadow:ident;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value $(; guard = $guard)?; shadow = $shadow);
+        $crate::fluid_let!($($rest)*);
+    };
+    // Multiple definitions (iteration), with Some value and options up to guard.
+    {
+        $(#[$attr:meta])*
+        $pub:vis static $name:ident: $type:ty = $value:expr; guard = $guard:expr;
+        $($rest:tt)*
+    } => {
+        $crate::fluid_let!($(#[$attr])* $pub static $name: $type = $value; guard = $guard);
+        $crate::fluid_let!($($rest)*);
+    };
     // Multiple definitions (iteration), with Some value.
     {
         $(#[$attr:meta])*
@@ -392,6 +1141,37 @@ macro_rules! fluid_let {
     {} => {};
 }
 
+/// Applies options of a single [`fluid_let!`](macro.fluid_let.html) definition.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fluid_let_options {
+    ($variable:expr, $type:ty $(; guard = $guard:expr)? $(; shadow = $shadow:ident)? $(; on_enter = $on_enter:expr)? $(; on_exit = $on_exit:expr)?) => {
+        $variable
+            $(.with_guard({
+                #[allow(clippy::ptr_arg)]
+                fn guard(value: &$type) -> Result<Option<Box<$type>>, $crate::InvalidValue> {
+                    ($guard)(value).map(|value| value.map(Box::new))
+                }
+                guard
+            }))?
+            $(.with_shadow($crate::shadow::$shadow))?
+            $(.with_on_enter({
+                #[allow(clippy::ptr_arg)]
+                fn on_enter(value: &$type) {
+                    ($on_enter)(value)
+                }
+                on_enter
+            }))?
+            $(.with_on_exit({
+                #[allow(clippy::ptr_arg)]
+                fn on_exit(value: &$type) {
+                    ($on_exit)(value)
+                }
+                on_exit
+            }))?
+    };
+}
+
 /// Binds a value to a dynamic variable.
 ///
 /// # Examples
@@ -429,10 +1209,11 @@ macro_rules! fluid_let {
 #[macro_export]
 macro_rules! fluid_set {
     ($variable:expr, $value:expr) => {
+        let _variable_ = &$variable;
         let _value_ = $value;
         // This is safe because the users do not get direct access to the guard
         // and are not able to drop it prematurely, thus maintaining invariants.
-        let _guard_ = unsafe { $variable.set_guard(&_value_) };
+        let _guard_ = unsafe { _variable_.set_guard(&_value_) };
     };
 }
 
@@ -441,52 +1222,313 @@ macro_rules! fluid_set {
 /// Declared and initialized by the [`fluid_let!`](macro.fluid_let.html) macro.
 ///
 /// See [crate-level documentation](index.html) for examples.
-pub struct DynamicVariable<T: 'static> {
+pub struct DynamicVariable<T: ?Sized + 'static> {
     cell: &'static LocalKey<DynamicCell<T>>,
+    global: Shared<T>,
+    init: Init<T>,
+    guard: Option<Guard<T>>,
+    shadow: Shadow,
+    on_enter: Option<fn(&T)>,
+    on_exit: Option<fn(&T)>,
+}
+
+/// Initial value of a dynamic variable.
+enum Init<T: ?Sized + 'static> {
+    None,
+    Static(&'static T),
+    Env(EnvDefault<T>),
 }
 
+// Values shared between threads are set only by methods which require T: Send + Sync.
+unsafe impl<T: ?Sized> Sync for DynamicVariable<T> {}
+
 /// A resettable reference.
 #[doc(hidden)]
-pub struct DynamicCell<T> {
-    cell: UnsafeCell<Option<*const T>>,
+pub struct DynamicCell<T: ?Sized> {
+    cell: UnsafeCell<Option<Bound<T>>>,
+    // Owned by BASES, so that cells do not need destructors.
+    base: Cell<Option<*const T>>,
+    base_readers: Cell<usize>,
+    sealed: Cell<bool>,
+}
+
+/// Value bound to `DynamicCell<T>`.
+enum Bound<T: ?Sized> {
+    Value(*const T),
+    Lazy(*const dyn Force<T>),
+}
+
+impl<T: ?Sized> Clone for Bound<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Bound<T> {}
+
+/// Base value of `DynamicCell<T>`, owned outside of the cell.
+struct BaseValue<T: ?Sized + 'static> {
+    cell: *const DynamicCell<T>,
+    value: Option<Box<T>>,
+}
+
+// Base values live in a separate thread-local, registered only when used.
+// Since dynamic cells have no destructors, variables can be read while other
+// thread-locals are being destroyed. Base values are simply gone by then.
+thread_local! {
+    static BASES: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Guard for reading the base value of `DynamicCell<T>`.
+struct DynamicCellReader<'a, T: ?Sized> {
+    cell: &'a DynamicCell<T>,
 }
 
 /// Guard setting a new value of `DynamicCell<T>`.
 #[doc(hidden)]
-pub struct DynamicCellGuard<'a, T> {
-    old_value: Option<*const T>,
+pub struct DynamicCellGuard<'a, T: ?Sized> {
+    old_value: Option<Bound<T>>,
+    old_sealed: bool,
+    owned: Option<*mut T>,
+    on_exit: Option<fn(&T)>,
     cell: &'a DynamicCell<T>,
 }
 
-impl<T> DynamicVariable<T> {
+impl<T: ?Sized> DynamicVariable<T> {
     /// Initialize a dynamic variable.
     ///
     /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
     #[doc(hidden)]
     pub const fn new(cell: &'static LocalKey<DynamicCell<T>>) -> Self {
-        Self { cell }
+        Self {
+            cell,
+            global: Shared::empty(),
+            init: Init::None,
+            guard: None,
+            shadow: Shadow::Allow,
+            on_enter: None,
+            on_exit: None,
+        }
+    }
+
+    /// Initialize a dynamic variable with static default value.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_static(cell: &'static LocalKey<DynamicCell<T>>, value: &'static T) -> Self {
+        Self {
+            cell,
+            global: Shared::empty(),
+            init: Init::Static(value),
+            guard: None,
+            shadow: Shadow::Allow,
+            on_enter: None,
+            on_exit: None,
+        }
+    }
+
+    /// Initialize a dynamic variable with default value from the environment.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_env(cell: &'static LocalKey<DynamicCell<T>>, env: EnvDefault<T>) -> Self
+    where
+        T: Send + Sync,
+    {
+        Self {
+            cell,
+            global: Shared::empty(),
+            init: Init::Env(env),
+            guard: None,
+            shadow: Shadow::Allow,
+            on_enter: None,
+            on_exit: None,
+        }
+    }
+
+    /// Set the guard of a dynamic variable.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_guard(mut self, guard: Guard<T>) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Set the shadowing policy of a dynamic variable.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_shadow(mut self, shadow: Shadow) -> Self {
+        self.shadow = shadow;
+        self
+    }
+
+    /// Set the hook called when a value is bound to a dynamic variable.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_on_enter(mut self, hook: fn(&T)) -> Self {
+        self.on_enter = Some(hook);
+        self
+    }
+
+    /// Set the hook called when a value is unbound from a dynamic variable.
+    ///
+    /// Use [`fluid_let!`](macro.fluid_let.html) macro to do this.
+    #[doc(hidden)]
+    pub const fn with_on_exit(mut self, hook: fn(&T)) -> Self {
+        self.on_exit = Some(hook);
+        self
     }
 
     /// Access current value of the dynamic variable.
     pub fn get<R>(&self, f: impl FnOnce(Option<&T>) -> R) -> R {
-        self.cell.with(|current| {
+        let mut f = Some(f);
+        let bound = self.cell.try_with(|current| {
             // This is safe because the lifetime of the reference returned by get()
             // is limited to this block so it cannot outlive any value set by set()
             // in the caller frames.
-            f(unsafe { current.get() })
-        })
+            if let Some(value) = unsafe { current.get() } {
+                return f.take().map(|f| f(Some(value)));
+            }
+            if let Some(value) = unsafe { current.base() } {
+                let _reader_ = current.read_base();
+                return f.take().map(|f| f(Some(value)));
+            }
+            None
+        });
+        if let Ok(Some(result)) = bound {
+            return result;
+        }
+        // The cell might be inaccessible while the thread is exiting,
+        // then there are no values bound in the current thread.
+        let f = f.expect("closure is called only if the value is bound");
+        if let Some(value) = self.global.load() {
+            return f(Some(&value));
+        }
+        #[cfg(feature = "config")]
+        {
+            if let Some(defaults) = config::Snapshot::current() {
+                if let Some(value) = defaults.get::<T>(self as *const Self as usize) {
+                    return f(Some(value));
+                }
+            }
+        }
+        match &self.init {
+            Init::None => f(None),
+            Init::Static(value) => f(Some(value)),
+            Init::Env(env) => f(env.get(self.guard).as_deref()),
+        }
     }
 
     /// Bind a new value to the dynamic variable.
+    ///
+    /// # Panics
+    ///
+    /// If the value is rejected by the guard of the variable,
+    /// or if the variable cannot be rebound (see [`try_set`](#method.try_set)).
     pub fn set<R>(&self, value: impl Borrow<T>, f: impl FnOnce() -> R) -> R {
+        match self.try_set(value, f) {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Bind a new value to the dynamic variable, if the guard accepts it.
+    ///
+    /// ```
+    /// # use fluid_let::{fluid_let, InvalidValue, SetError};
+    /// fn validate_port(port: &u16) -> Result<Option<u16>, InvalidValue> {
+    ///     if *port < 1024 {
+    ///         return Err(InvalidValue::new("privileged port"));
+    ///     }
+    ///     Ok(None)
+    /// }
+    ///
+    /// fluid_let!(static PORT: u16; guard = validate_port);
+    ///
+    /// assert_eq!(PORT.try_set(8080, || PORT.copied()), Ok(Some(8080)));
+    /// assert!(matches!(PORT.try_set(80, || PORT.copied()), Err(SetError::Invalid(_))));
+    /// ```
+    ///
+    /// Binding also fails if the current value has been bound with [`set_final`](#method.set_final),
+    /// or if the variable is declared with `shadow = deny` and already has a value bound
+    /// in the current thread:
+    ///
+    /// ```
+    /// # use fluid_let::{fluid_let, SetError};
+    /// # struct Principal;
+    /// fluid_let!(static PRINCIPAL: Principal; shadow = deny);
+    ///
+    /// PRINCIPAL.set(Principal, || {
+    ///     assert!(matches!(PRINCIPAL.try_set(Principal, || {}), Err(SetError::Shadowing)));
+    /// });
+    /// ```
+    pub fn try_set<R>(&self, value: impl Borrow<T>, f: impl FnOnce() -> R) -> Result<R, SetError> {
+        self.try_bind(value.borrow(), false, f)
+    }
+
+    /// Bind a final value to the dynamic variable.
+    ///
+    /// The variable cannot be rebound within the dynamic extent of `f`:
+    /// [`set`](#method.set) panics and [`try_set`](#method.try_set) returns an error.
+    ///
+    /// ```
+    /// # use fluid_let::{fluid_let, SetError};
+    /// fluid_let!(static LEVEL: u32);
+    ///
+    /// LEVEL.set_final(1, || {
+    ///     assert_eq!(LEVEL.try_set(2, || LEVEL.copied()), Err(SetError::Final));
+    ///     assert_eq!(LEVEL.copied(), Some(1));
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Same as [`set`](#method.set).
+    pub fn set_final<R>(&self, value: impl Borrow<T>, f: impl FnOnce() -> R) -> R {
+        match self.try_bind(value.borrow(), true, f) {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn try_bind<R>(&self, value: &T, seal: bool, f: impl FnOnce() -> R) -> Result<R, SetError> {
+        let converted = self.check(value)?;
+        let value = converted.as_deref().unwrap_or(value);
         self.cell.with(|current| {
+            self.check_policy(current)?;
             // This is safe because the guard returned by set() is guaranteed to be
             // dropped after the thunk returns and before anything else executes.
-            let _guard_ = unsafe { current.set(value.borrow()) };
-            f()
+            let mut guard = unsafe { current.set(value) };
+            if seal {
+                current.make_final();
+            }
+            self.enter(&mut guard);
+            Ok(f())
         })
     }
 
+    /// Check whether the variable can be rebound in the current thread.
+    fn check_policy(&self, current: &DynamicCell<T>) -> Result<(), SetError> {
+        if current.is_final() {
+            return Err(SetError::Final);
+        }
+        if self.shadow == Shadow::Deny && current.is_bound() {
+            return Err(SetError::Shadowing);
+        }
+        Ok(())
+    }
+
+    /// Check a new value with the guard, returning the converted value, if any.
+    pub(crate) fn check(&self, value: &T) -> Result<Option<Box<T>>, InvalidValue> {
+        match self.guard {
+            Some(guard) => guard(value),
+            None => Ok(None),
+        }
+    }
+
     /// Bind a new value to the dynamic variable.
     ///
     /// # Safety
@@ -496,14 +1538,151 @@ impl<T> DynamicVariable<T> {
     /// If the variable is assigned another value while this guard is alive, it must
     /// not be dropped until that new assignment is undone.
     #[doc(hidden)]
-    pub unsafe fn set_guard(&self, value: &T) -> DynamicCellGuard<T> {
+    pub unsafe fn set_guard(&self, value: &T) -> DynamicCellGuard<'_, T> {
         // We use transmute to extend the lifetime or "current" to that of "value".
         // This is really the case when assignments are properly scoped.
-        unsafe fn extend_lifetime<'a, 'b, T>(r: &'a T) -> &'b T {
+        unsafe fn extend_lifetime<'b, T: ?Sized>(r: &T) -> &'b T {
             mem::transmute(r)
         }
+        let converted = match self.check(value) {
+            Ok(converted) => converted,
+            Err(e) => panic!("{}", SetError::from(e)),
+        };
+        self.cell.with(|current| {
+            if let Err(e) = self.check_policy(current) {
+                panic!("{}", e);
+            }
+            let mut guard = match converted {
+                Some(value) => extend_lifetime(current).set_owned(value),
+                None => extend_lifetime(current).set(value),
+            };
+            self.enter(&mut guard);
+            guard
+        })
+    }
+
+    /// Run hooks for a new binding, making the guard run hooks when it is undone.
+    fn enter(&self, guard: &mut DynamicCellGuard<'_, T>) {
+        if let Some(on_enter) = self.on_enter {
+            // This is safe because the value is bound while the guard is alive.
+            if let Some(value) = unsafe { guard.cell.get() } {
+                on_enter(value);
+            }
+        }
+        guard.on_exit = self.on_exit;
+    }
+}
+
+impl<T> DynamicVariable<T> {
+    /// Bind a lazily computed value to the dynamic variable.
+    ///
+    /// The value is computed by `init` when the variable is accessed for the first time
+    /// within the dynamic extent of `f`. If it is never accessed, `init` is never called.
+    /// The value is dropped when `f` returns.
+    ///
+    /// ```
+    /// # use fluid_let::fluid_let;
+    /// # fn build_table() -> Vec<u32> { vec![1, 2, 3] }
+    /// fluid_let!(static TABLE: Vec<u32>);
+    ///
+    /// TABLE.set_lazy(build_table, || {
+    ///     // build_table() is called here, only once
+    ///     assert_eq!(TABLE.get(|table| table.unwrap().len()), 3);
+    ///     assert_eq!(TABLE.get(|table| table.unwrap()[0]), 1);
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the variable cannot be rebound (see [`try_set`](#method.try_set)).
+    ///
+    /// Accessing the variable from `init` panics. If `init` panics, or the computed value
+    /// is rejected by the guard, further accesses to the variable in this extent will panic.
+    pub fn set_lazy<R>(&self, init: impl FnOnce() -> T, f: impl FnOnce() -> R) -> R {
+        let value = Lazy::new(|| self.checked(init()), self.on_enter);
+        self.cell.with(|current| {
+            if let Err(e) = self.check_policy(current) {
+                panic!("{}", e);
+            }
+            // This is safe because the guard returned by set_lazy() is guaranteed to be
+            // dropped after the thunk returns and before anything else executes.
+            let mut guard = unsafe { current.set_lazy(&value) };
+            guard.on_exit = self.on_exit;
+            f()
+        })
+    }
+
+    /// Bind a new value to the dynamic variable for the rest of the current thread's life.
+    ///
+    /// This value is used when there is no value bound by [`set`](#method.set)
+    /// in the current thread. It is useful for initializing worker threads,
+    /// where there is no closure to wrap the thread body in.
+    ///
+    /// Returns the previous value bound this way.
+    ///
+    /// # Panics
+    ///
+    /// If the previous value is being accessed by [`get`](#method.get),
+    /// or if the value is rejected by the guard of the variable.
+    pub fn set_for_thread(&self, value: T) -> Option<T> {
+        let value = self.checked(value);
+        self.cell
+            .with(|current| current.replace_base(Some(Box::new(value))))
+            .map(|value| *value)
+    }
+
+    /// Unbind the value set for the current thread by [`set_for_thread`](#method.set_for_thread).
+    ///
+    /// Returns the previous value.
+    ///
+    /// # Panics
+    ///
+    /// If the previous value is being accessed by [`get`](#method.get).
+    pub fn reset_for_thread(&self) -> Option<T> {
         self.cell
-            .with(|current| extend_lifetime(current).set(value))
+            .with(|current| current.replace_base(None))
+            .map(|value| *value)
+    }
+
+    /// Check an owned value with the guard, returning the converted value.
+    ///
+    /// # Panics
+    ///
+    /// If the value is rejected by the guard.
+    fn checked(&self, value: T) -> T {
+        match self.check(&value) {
+            Ok(None) => value,
+            Ok(Some(value)) => *value,
+            Err(e) => panic!("{}", SetError::from(e)),
+        }
+    }
+}
+
+impl<T: Send + Sync> DynamicVariable<T> {
+    /// Set the process-wide default value of the dynamic variable.
+    ///
+    /// The default value is shared by all threads. It is used when no value is bound
+    /// to the variable in the current thread. Default values take precedence over
+    /// initial values provided in [`fluid_let!`](macro.fluid_let.html) declarations.
+    ///
+    /// With `"config"` feature, default values set here also take precedence over
+    /// defaults loaded from configuration files. These are kept separately,
+    /// so reloading configuration does not change or remove default values set here.
+    ///
+    /// Returns the previous default value.
+    ///
+    /// # Panics
+    ///
+    /// If the value is rejected by the guard of the variable.
+    pub fn set_global_default(&self, value: T) -> Option<Arc<T>> {
+        self.global.store(Some(Arc::new(self.checked(value))))
+    }
+
+    /// Remove the process-wide default value of the dynamic variable.
+    ///
+    /// Returns the previous default value.
+    pub fn reset_global_default(&self) -> Option<Arc<T>> {
+        self.global.store(None)
     }
 }
 
@@ -521,19 +1700,14 @@ impl<T: Copy> DynamicVariable<T> {
     }
 }
 
-impl<T> DynamicCell<T> {
+impl<T: ?Sized> DynamicCell<T> {
     /// Makes a new empty cell.
     pub fn empty() -> Self {
         DynamicCell {
             cell: UnsafeCell::new(None),
-        }
-    }
-
-    /// Makes a new cell with value.
-    #[cfg(feature = "static-init")]
-    pub fn with_static(value: &'static T) -> Self {
-        DynamicCell {
-            cell: UnsafeCell::new(Some(value)),
+            base: Cell::new(None),
+            base_readers: Cell::new(0),
+            sealed: Cell::new(false),
         }
     }
 
@@ -544,7 +1718,11 @@ impl<T> DynamicCell<T> {
     /// The returned reference is safe to use during the lifetime of a corresponding guard
     /// returned by a `set()` call. Ensure that this reference does not outlive it.
     unsafe fn get(&self) -> Option<&T> {
-        (&*self.cell.get()).map(|p| &*p)
+        match *self.cell.get() {
+            Some(Bound::Value(value)) => Some(&*value),
+            Some(Bound::Lazy(lazy)) => Some((*lazy).force()),
+            None => None,
+        }
     }
 
     /// Temporarily set a new value of the cell.
@@ -556,21 +1734,146 @@ impl<T> DynamicCell<T> {
     ///
     /// You have to ensure that the guard for the previous value is dropped after this one.
     /// That is, they must be dropped in strict LIFO order, like a call stack.
-    unsafe fn set(&self, value: &T) -> DynamicCellGuard<T> {
+    unsafe fn set(&self, value: &T) -> DynamicCellGuard<'_, T> {
+        self.bind(Bound::Value(value))
+    }
+
+    /// Temporarily set a new lazily computed value of the cell.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `set()`.
+    unsafe fn set_lazy(&self, value: &dyn Force<T>) -> DynamicCellGuard<'_, T> {
+        // We use transmute to erase the lifetime of the trait object.
+        // The guard ensures that the cell does not refer to it after it is gone.
+        let value = mem::transmute::<*const (dyn Force<T> + '_), *const dyn Force<T>>(value);
+        self.bind(Bound::Lazy(value))
+    }
+
+    /// Temporarily set a new value of the cell, owned by the guard.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `set()`.
+    unsafe fn set_owned(&self, value: Box<T>) -> DynamicCellGuard<'_, T> {
+        let value = Box::into_raw(value);
+        let mut guard = self.bind(Bound::Value(value));
+        guard.owned = Some(value);
+        guard
+    }
+
+    unsafe fn bind(&self, value: Bound<T>) -> DynamicCellGuard<'_, T> {
         DynamicCellGuard {
-            old_value: mem::replace(&mut *self.cell.get(), Some(value)),
+            old_value: (*self.cell.get()).replace(value),
+            old_sealed: self.sealed.replace(false),
+            owned: None,
+            on_exit: None,
             cell: self,
         }
     }
+
+    /// Returns true if the cell has a value set.
+    fn is_bound(&self) -> bool {
+        // This is safe because the value is not accessed.
+        unsafe { (*self.cell.get()).is_some() }
+    }
+
+    /// Returns true if the current value cannot be replaced.
+    fn is_final(&self) -> bool {
+        self.sealed.get()
+    }
+
+    /// Prevent the current value from being replaced until its guard is dropped.
+    fn make_final(&self) {
+        self.sealed.set(true);
+    }
+
+    /// Access the base value of the cell, if any.
+    ///
+    /// # Safety
+    ///
+    /// The returned reference is safe to use while a guard returned by `read_base()`
+    /// is live. Ensure that this reference does not outlive it.
+    unsafe fn base(&self) -> Option<&T> {
+        self.base.get().map(|value| &*value)
+    }
+
+    /// Prevent the base value from being replaced while the returned guard is live.
+    fn read_base(&self) -> DynamicCellReader<'_, T> {
+        self.base_readers.set(self.base_readers.get() + 1);
+        DynamicCellReader { cell: self }
+    }
 }
 
-impl<'a, T> Drop for DynamicCellGuard<'a, T> {
+impl<T: ?Sized + 'static> DynamicCell<T> {
+    /// Replace the base value of the cell, returning the previous one.
+    ///
+    /// The cell must be a thread-local.
+    ///
+    /// # Panics
+    ///
+    /// If the base value is being read.
+    fn replace_base(&self, value: Option<Box<T>>) -> Option<Box<T>> {
+        if self.base_readers.get() > 0 {
+            panic!("cannot replace thread-local value of a dynamic variable while it is in use");
+        }
+        let address = self as *const Self as usize;
+        let old = BASES.with(|bases| bases.borrow_mut().remove(&address));
+        // Dropping the old value clears the pointer to it, so set the new one afterwards.
+        let old = old.and_then(|old| {
+            let mut old = old
+                .downcast::<BaseValue<T>>()
+                .expect("cell address must match value type");
+            old.value.take()
+        });
+        if let Some(value) = value {
+            self.base.set(Some(&*value as *const T));
+            let value: Box<dyn Any> = Box::new(BaseValue {
+                cell: self,
+                value: Some(value),
+            });
+            BASES.with(|bases| bases.borrow_mut().insert(address, value));
+        }
+        old
+    }
+}
+
+impl<T: ?Sized> Drop for BaseValue<T> {
+    fn drop(&mut self) {
+        // This is safe because cells are thread-locals without destructors,
+        // so they outlive base values stored in another thread-local.
+        unsafe { (*self.cell).base.set(None) };
+    }
+}
+
+impl<'a, T: ?Sized> Drop for DynamicCellReader<'a, T> {
+    fn drop(&mut self) {
+        self.cell.base_readers.set(self.cell.base_readers.get() - 1);
+    }
+}
+
+impl<'a, T: ?Sized> Drop for DynamicCellGuard<'a, T> {
     fn drop(&mut self) {
         // We can safely drop the new value of a cell and restore the old one provided that
         // get() and set() methods of DynamicCell are used correctly. That is, there must be
         // no users of the new value which is about to be destroyed.
         unsafe {
-            *self.cell.cell.get() = self.old_value.take();
+            let value = mem::replace(&mut *self.cell.cell.get(), self.old_value.take());
+            self.cell.sealed.set(self.old_sealed);
+            let owned = self.owned.take().map(|value| Box::from_raw(value));
+            if let Some(on_exit) = self.on_exit {
+                // The exit hook is called with the value being unbound, which is still alive.
+                // Lazy values are passed only if they have been computed.
+                let value = match (&owned, value) {
+                    (Some(owned), _) => Some(&**owned),
+                    (None, Some(Bound::Value(value))) => Some(&*value),
+                    (None, Some(Bound::Lazy(lazy))) => (*lazy).computed(),
+                    (None, None) => None,
+                };
+                if let Some(value) = value {
+                    on_exit(value);
+                }
+            }
         }
     }
 }
@@ -692,6 +1995,327 @@ mod tests {
         })
     }
 
+    #[test]
+    fn global_defaults() {
+        fluid_let!(static NUMBER: i32);
+
+        assert_eq!(NUMBER.set_global_default(1), None);
+        assert_eq!(NUMBER.copied(), Some(1));
+
+        NUMBER.set(2, || {
+            assert_eq!(NUMBER.copied(), Some(2));
+            let t = thread::spawn(|| {
+                assert_eq!(NUMBER.copied(), Some(1));
+                assert_eq!(NUMBER.set_global_default(3).as_deref(), Some(&1));
+            });
+            t.join().unwrap();
+            assert_eq!(NUMBER.copied(), Some(2));
+        });
+        assert_eq!(NUMBER.copied(), Some(3));
+
+        assert_eq!(NUMBER.reset_global_default().as_deref(), Some(&3));
+        assert_eq!(NUMBER.copied(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "static-init")]
+    fn global_defaults_override_static_initializer() {
+        fluid_let!(static NUMBER: i32 = 42);
+
+        NUMBER.set_global_default(1);
+        assert_eq!(NUMBER.copied(), Some(1));
+        NUMBER.reset_global_default();
+        assert_eq!(NUMBER.copied(), Some(42));
+    }
+
+    #[test]
+    fn thread_bindings() {
+        fluid_let!(static NUMBER: i32);
+
+        assert_eq!(NUMBER.set_for_thread(1), None);
+        assert_eq!(NUMBER.copied(), Some(1));
+        NUMBER.set(2, || {
+            assert_eq!(NUMBER.copied(), Some(2));
+            assert_eq!(NUMBER.set_for_thread(3), Some(1));
+            assert_eq!(NUMBER.copied(), Some(2));
+        });
+        assert_eq!(NUMBER.copied(), Some(3));
+
+        let t = thread::spawn(|| {
+            assert_eq!(NUMBER.copied(), None);
+            NUMBER.set_for_thread(4);
+            assert_eq!(NUMBER.copied(), Some(4));
+        });
+        t.join().unwrap();
+
+        assert_eq!(NUMBER.reset_for_thread(), Some(3));
+        assert_eq!(NUMBER.copied(), None);
+    }
+
+    #[test]
+    fn access_from_thread_local_destructors() {
+        use std::sync::Mutex;
+
+        fluid_let! {
+            static LEVEL: i32;
+            static WORKER: i32;
+        }
+        static SEEN: Mutex<Vec<(Option<i32>, Option<i32>)>> = Mutex::new(Vec::new());
+
+        struct Logger;
+
+        impl Drop for Logger {
+            fn drop(&mut self) {
+                let seen = (LEVEL.copied(), WORKER.copied());
+                SEEN.lock().unwrap().push(seen);
+            }
+        }
+
+        thread_local! {
+            static LOGGER: Logger = const { Logger };
+        }
+
+        LEVEL.set_global_default(1);
+        thread::spawn(|| {
+            LOGGER.with(|_| {});
+            WORKER.set_for_thread(2);
+            LEVEL.set(3, || assert_eq!(LEVEL.copied(), Some(3)));
+        })
+        .join()
+        .unwrap();
+
+        // The thread-local value might be already dropped, depending on destruction order.
+        let seen = SEEN.lock().unwrap();
+        assert!(matches!(seen[..], [(Some(1), Some(2) | None)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "while it is in use")]
+    fn thread_bindings_in_use() {
+        fluid_let!(static NUMBER: i32);
+
+        NUMBER.set_for_thread(1);
+        NUMBER.get(|_| NUMBER.set_for_thread(2));
+    }
+
+    #[test]
+    fn unsized_types() {
+        use std::cell::RefCell;
+        use std::io::Write;
+
+        fluid_let! {
+            static PREFIX: str;
+            static NUMBERS: [i32];
+            static CALLBACK: dyn Fn(i32) -> i32;
+            static OUTPUT: RefCell<dyn Write>;
+        }
+
+        let prefix = String::from("> ");
+        PREFIX.set(&prefix[..], || {
+            PREFIX.get(|current| assert_eq!(current, Some("> ")));
+            fluid_set!(PREFIX, "# ");
+            PREFIX.get(|current| assert_eq!(current, Some("# ")));
+        });
+
+        NUMBERS.set(&[1, 2, 3][..], || {
+            NUMBERS.get(|current| assert_eq!(current, Some(&[1, 2, 3][..])));
+        });
+
+        let double = |x| x * 2;
+        CALLBACK.set(&double as &dyn Fn(i32) -> i32, || {
+            CALLBACK.get(|current| assert_eq!(current.map(|f| f(21)), Some(42)));
+        });
+
+        let buffer = RefCell::new(Vec::new());
+        OUTPUT.set(&buffer as &RefCell<dyn Write>, || {
+            OUTPUT.get(|current| write!(current.unwrap().borrow_mut(), "text").unwrap());
+        });
+        assert_eq!(buffer.into_inner(), b"text");
+    }
+
+    #[test]
+    fn lazy_bindings() {
+        use std::rc::Rc;
+
+        fluid_let!(static VALUE: Rc<i32>);
+
+        let calls = Cell::new(0);
+        let compute = || {
+            calls.set(calls.get() + 1);
+            Rc::new(42)
+        };
+
+        VALUE.set_lazy(compute, || {});
+        assert_eq!(calls.get(), 0);
+
+        let value = VALUE.set_lazy(compute, || {
+            assert_eq!(VALUE.get(|value| **value.unwrap()), 42);
+            VALUE.set(Rc::new(1), || assert_eq!(VALUE.get(|v| **v.unwrap()), 1));
+            VALUE.cloned().unwrap()
+        });
+        assert_eq!(calls.get(), 1);
+        assert_eq!(VALUE.cloned(), None);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "lazy value is being computed")]
+    fn lazy_bindings_reentrancy() {
+        fluid_let!(static VALUE: i32);
+
+        VALUE.set_lazy(|| VALUE.copied().unwrap_or(0) + 1, || VALUE.copied());
+    }
+
+    #[test]
+    fn guards() {
+        fn normalize(name: &str) -> Result<Option<String>, InvalidValue> {
+            if name.is_empty() {
+                return Err(InvalidValue::new("empty name"));
+            }
+            if name.chars().any(char::is_uppercase) {
+                return Ok(Some(name.to_lowercase()));
+            }
+            Ok(None)
+        }
+
+        fluid_let! {
+            static NAME: String; guard = normalize;
+        }
+
+        assert_eq!(
+            NAME.set("name".to_owned(), || NAME.cloned()),
+            Some("name".to_owned())
+        );
+        assert_eq!(
+            NAME.set("Name".to_owned(), || NAME.cloned()),
+            Some("name".to_owned())
+        );
+        assert_eq!(
+            NAME.try_set(String::new(), || NAME.cloned()),
+            Err(SetError::Invalid(InvalidValue::new("empty name")))
+        );
+        {
+            fluid_set!(NAME, "NAME".to_owned());
+            assert_eq!(NAME.cloned(), Some("name".to_owned()));
+        }
+        NAME.set_lazy(
+            || "Lazy".to_owned(),
+            || assert_eq!(NAME.cloned(), Some("lazy".to_owned())),
+        );
+
+        NAME.set_for_thread("Thread".to_owned());
+        assert_eq!(NAME.cloned(), Some("thread".to_owned()));
+        NAME.reset_for_thread();
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid value of dynamic variable: empty name")]
+    fn guards_reject_invalid_values() {
+        fn non_empty(name: &str) -> Result<Option<String>, InvalidValue> {
+            match name.is_empty() {
+                true => Err(InvalidValue::new("empty name")),
+                false => Ok(None),
+            }
+        }
+
+        fluid_let! {
+            static NAME: String; guard = non_empty;
+        }
+
+        NAME.set(String::new(), || {});
+    }
+
+    #[test]
+    fn rebinding_policies() {
+        fn positive(value: &i32) -> Result<Option<i32>, InvalidValue> {
+            match *value > 0 {
+                true => Ok(None),
+                false => Err(InvalidValue::new("not positive")),
+            }
+        }
+
+        fluid_let! {
+            static DENIED: i32; shadow = deny;
+            static GUARDED: i32; guard = positive; shadow = deny;
+            static ALLOWED: i32; shadow = allow;
+        }
+
+        DENIED.set(1, || {
+            assert_eq!(DENIED.try_set(2, || {}), Err(SetError::Shadowing));
+            thread::spawn(|| DENIED.set(3, || assert_eq!(DENIED.copied(), Some(3))))
+                .join()
+                .unwrap();
+            assert_eq!(DENIED.copied(), Some(1));
+        });
+        DENIED.set(4, || assert_eq!(DENIED.copied(), Some(4)));
+
+        assert!(matches!(
+            GUARDED.try_set(0, || {}),
+            Err(SetError::Invalid(_))
+        ));
+        GUARDED.set(1, || {
+            assert_eq!(GUARDED.try_set(2, || {}), Err(SetError::Shadowing));
+        });
+
+        ALLOWED.set_final(1, || {
+            assert_eq!(ALLOWED.try_set(2, || {}), Err(SetError::Final));
+            assert_eq!(ALLOWED.copied(), Some(1));
+        });
+        ALLOWED.set(1, || {
+            ALLOWED.set(2, || assert_eq!(ALLOWED.copied(), Some(2)))
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "dynamic variable has a final value bound")]
+    fn final_bindings() {
+        fluid_let!(static LEVEL: i32);
+
+        LEVEL.set_final(1, || {
+            fluid_set!(LEVEL, 2);
+        });
+    }
+
+    #[test]
+    fn hooks() {
+        use std::cell::RefCell;
+        use std::panic::{self, AssertUnwindSafe};
+
+        thread_local! {
+            static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        }
+        fn log(event: &str, value: &i32) {
+            LOG.with(|log| log.borrow_mut().push(format!("{} {}", event, value)));
+        }
+        fn take_log() -> Vec<String> {
+            LOG.with(|log| log.borrow_mut().split_off(0))
+        }
+
+        fluid_let! {
+            static VALUE: i32; on_enter = |v| log("enter", v); on_exit = |v| log("exit", v);
+        }
+
+        VALUE.set(1, || {
+            VALUE.set(2, || log("body", &VALUE.copied().unwrap()));
+        });
+        assert_eq!(
+            take_log(),
+            ["enter 1", "enter 2", "body 2", "exit 2", "exit 1"]
+        );
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            fluid_set!(VALUE, 3);
+            panic!("oops");
+        }));
+        assert!(result.is_err());
+        assert_eq!(take_log(), ["enter 3", "exit 3"]);
+
+        VALUE.set_lazy(|| 4, || {});
+        assert!(take_log().is_empty());
+        VALUE.set_lazy(|| 5, || VALUE.copied());
+        assert_eq!(take_log(), ["enter 5", "exit 5"]);
+    }
+
     #[test]
     fn convenience_accessors() {
         fluid_let!(static ENABLED: bool);
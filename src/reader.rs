@@ -0,0 +1,109 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Read-only access to dynamic variables.
+
+use crate::DynamicVariable;
+
+/// Read-only handle of a dynamic variable.
+///
+/// Anyone who can name a [`DynamicVariable`](struct.DynamicVariable.html) can also bind
+/// new values to it. A library may keep the variable private and export a reader instead,
+/// reserving the right to bind values for itself:
+///
+/// ```
+/// mod library {
+///     use fluid_let::{fluid_let, Reader};
+///
+///     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///     pub enum Mode {
+///         Fast,
+///         Safe,
+///     }
+///
+///     fluid_let!(static MODE: Mode);
+///
+///     pub static CURRENT_MODE: Reader<Mode> = MODE.reader();
+///
+///     pub fn with_safe_mode(f: impl FnOnce()) {
+///         MODE.set(Mode::Safe, f);
+///     }
+/// }
+///
+/// use library::{Mode, CURRENT_MODE};
+///
+/// library::with_safe_mode(|| {
+///     assert_eq!(CURRENT_MODE.copied(), Some(Mode::Safe));
+/// });
+/// ```
+///
+/// Readers cannot bind values:
+///
+/// ```compile_fail
+/// # use fluid_let::{fluid_let, Reader};
+/// fluid_let!(static MODE: u32);
+/// static CURRENT_MODE: Reader<u32> = MODE.reader();
+///
+/// CURRENT_MODE.set(1, || {});
+/// ```
+pub struct Reader<T: ?Sized + 'static> {
+    variable: &'static DynamicVariable<T>,
+}
+
+impl<T: ?Sized> DynamicVariable<T> {
+    /// Make a read-only handle of the dynamic variable.
+    pub const fn reader(&'static self) -> Reader<T> {
+        Reader { variable: self }
+    }
+}
+
+impl<T: ?Sized> Reader<T> {
+    /// Access current value of the dynamic variable.
+    pub fn get<R>(&self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        self.variable.get(f)
+    }
+}
+
+impl<T: Clone> Reader<T> {
+    /// Clone current value of the dynamic variable.
+    pub fn cloned(&self) -> Option<T> {
+        self.variable.cloned()
+    }
+}
+
+impl<T: Copy> Reader<T> {
+    /// Copy current value of the dynamic variable.
+    pub fn copied(&self) -> Option<T> {
+        self.variable.copied()
+    }
+}
+
+impl<T: ?Sized> Clone for Reader<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Reader<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fluid_let;
+
+    fluid_let!(static NUMBER: i32);
+
+    static READER: Reader<i32> = NUMBER.reader();
+
+    #[test]
+    fn read_access() {
+        assert_eq!(READER.copied(), None);
+        NUMBER.set(1, || {
+            let reader = READER;
+            assert_eq!(reader.copied(), Some(1));
+            assert_eq!(reader.get(|value| value.map(|v| v + 1)), Some(2));
+        });
+        assert_eq!(READER.cloned(), None);
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Registry of named dynamic variables.
+//!
+//! Dynamic variables can be _exported_ under a name, making it possible to set them
+//! by name at runtime, e.g., from a [configuration file](../config/index.html)
+//! or [command line](../cli/index.html):
+//!
+//! ```
+//! use fluid_let::{fluid_let, registry};
+//!
+//! fluid_let!(pub static HASH_LENGTH: usize);
+//!
+//! registry::export_from_str("hash_length", &HASH_LENGTH);
+//! ```
+//!
+//! Values of variables exported with [`export_from_str`] are parsed with `FromStr`.
+//! If `"config"` feature is enabled, variables can be exported with [`export`] instead,
+//! then their values are deserialized with serde. A variable may be exported both ways.
+//!
+//! Exporting is explicit and usually done once at startup.
+//! Each name can be used by one dynamic variable only.
+//!
+//! Exported variables may receive process-wide default values
+//! (e.g., from [`config::Defaults`](../config/struct.Defaults.html)),
+//! therefore their types must be `Send + Sync`.
+//!
+//! [`export_from_str`]: fn.export_from_str.html
+//! [`export`]: fn.export.html
+
+use std::any::{self, Any};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "config")]
+use serde::de::DeserializeOwned;
+
+use crate::{DynamicVariable, SetError};
+
+/// Parses a value of an exported variable from string.
+pub(crate) type Parse = fn(&str) -> Result<Box<dyn Any>, String>;
+
+/// Parses a value of an exported variable.
+#[cfg(feature = "config")]
+pub(crate) type Deserialize = fn(serde_json::Value) -> Result<Box<dyn Any>, String>;
+
+/// Exported dynamic variable.
+#[derive(Clone)]
+pub(crate) struct Variable {
+    pub(crate) name: &'static str,
+    pub(crate) type_name: &'static str,
+    pub(crate) binding: &'static dyn Binding,
+    pub(crate) parse: Option<Parse>,
+    #[cfg(feature = "config")]
+    pub(crate) deserialize: Option<Deserialize>,
+}
+
+/// Value for an exported variable.
+pub(crate) type Assignment = (Variable, Box<dyn Any>);
+
+/// Type-erased dynamic variable.
+pub(crate) trait Binding: Sync {
+    /// Binds a value to the variable for the extent of the body.
+    ///
+    /// The value must have the same type as the variable.
+    /// The body is not called if the variable cannot be bound.
+    fn bind(&self, value: &dyn Any, body: &mut dyn FnMut()) -> Result<(), SetError>;
+
+    /// Checks a new value with the guard of the variable, converting it if necessary.
+    ///
+    /// The value must have the same type as the variable.
+    fn validate(&self, value: Box<dyn Any>) -> Result<Box<dyn Any>, String>;
+
+    /// Prepares a value to be shared between threads as a default value of the variable.
+    ///
+    /// The value must have the same type as the variable.
+    #[cfg_attr(not(feature = "config"), allow(dead_code))]
+    fn share(&self, value: Box<dyn Any>) -> Arc<dyn Any + Send + Sync>;
+
+    /// Address of the variable, to tell variables apart.
+    fn address(&self) -> *const ();
+}
+
+impl<T: Send + Sync + 'static> Binding for DynamicVariable<T> {
+    fn bind(&self, value: &dyn Any, body: &mut dyn FnMut()) -> Result<(), SetError> {
+        let value = value
+            .downcast_ref::<T>()
+            .expect("value type must match variable type");
+        self.try_set(value, body)
+    }
+
+    fn validate(&self, value: Box<dyn Any>) -> Result<Box<dyn Any>, String> {
+        let value = value
+            .downcast::<T>()
+            .expect("value type must match variable type");
+        match self.check(&value) {
+            Ok(None) => Ok(value),
+            Ok(Some(value)) => Ok(value),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn share(&self, value: Box<dyn Any>) -> Arc<dyn Any + Send + Sync> {
+        let value = value
+            .downcast::<T>()
+            .expect("value type must match variable type");
+        Arc::new(value)
+    }
+
+    fn address(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+}
+
+static REGISTRY: Mutex<BTreeMap<&'static str, Variable>> = Mutex::new(BTreeMap::new());
+
+/// Exports a dynamic variable under given name, with values parsed by `FromStr`.
+///
+/// # Panics
+///
+/// If another dynamic variable has been already exported under the same name.
+pub fn export_from_str<T>(name: &'static str, variable: &'static DynamicVariable<T>)
+where
+    T: FromStr + Send + Sync + 'static,
+    T::Err: fmt::Display,
+{
+    fn parse<T>(value: &str) -> Result<Box<dyn Any>, String>
+    where
+        T: FromStr + 'static,
+        T::Err: fmt::Display,
+    {
+        match value.parse::<T>() {
+            Ok(value) => Ok(Box::new(value)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    register(name, variable, |entry| {
+        entry.parse = Some(parse::<T>);
+    });
+}
+
+/// Exports a dynamic variable under given name, with values parsed by serde.
+///
+/// # Panics
+///
+/// If another dynamic variable has been already exported under the same name.
+#[cfg(feature = "config")]
+pub fn export<T>(name: &'static str, variable: &'static DynamicVariable<T>)
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    fn deserialize<T: DeserializeOwned + 'static>(
+        value: serde_json::Value,
+    ) -> Result<Box<dyn Any>, String> {
+        match serde_json::from_value::<T>(value) {
+            Ok(value) => Ok(Box::new(value)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    register(name, variable, |entry| {
+        entry.deserialize = Some(deserialize::<T>);
+    });
+}
+
+fn register<T: Send + Sync + 'static>(
+    name: &'static str,
+    variable: &'static DynamicVariable<T>,
+    update: impl FnOnce(&mut Variable),
+) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = registry.entry(name).or_insert_with(|| Variable {
+        name,
+        type_name: any::type_name::<T>(),
+        binding: variable,
+        parse: None,
+        #[cfg(feature = "config")]
+        deserialize: None,
+    });
+    if entry.binding.address() != variable.address() {
+        panic!("dynamic variable {:?} is already exported", name);
+    }
+    update(entry);
+}
+
+/// Looks up an exported variable by name.
+pub(crate) fn lookup(name: &str) -> Option<Variable> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.get(name).cloned()
+}
+
+/// Lists all exported variables, ordered by name.
+pub(crate) fn all() -> Vec<Variable> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.values().cloned().collect()
+}
+
+/// Binds values to exported variables for the extent of the body.
+///
+/// Values must have the same types as corresponding variables. If a variable is given
+/// multiple values, the last one is used. If some variable cannot be bound, the body
+/// is not called and the name of the variable is returned with the error.
+pub(crate) fn bind_all<R>(
+    values: &[Assignment],
+    body: impl FnOnce() -> R,
+) -> Result<R, (&'static str, SetError)> {
+    fn bind_rest(
+        values: &[Assignment],
+        body: &mut dyn FnMut(),
+    ) -> Result<(), (&'static str, SetError)> {
+        match values.split_first() {
+            Some(((variable, _), rest)) if rest.iter().any(|(v, _)| v.name == variable.name) => {
+                bind_rest(rest, body)
+            }
+            Some(((variable, value), rest)) => {
+                let mut result = Ok(());
+                variable
+                    .binding
+                    .bind(value.as_ref(), &mut || result = bind_rest(rest, body))
+                    .map_err(|e| (variable.name, e))?;
+                result
+            }
+            None => {
+                body();
+                Ok(())
+            }
+        }
+    }
+    let mut body = Some(body);
+    let mut result = None;
+    bind_rest(values, &mut || result = body.take().map(|body| body()))?;
+    Ok(result.expect("body must be called"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fluid_let;
+
+    #[test]
+    fn export_and_bind() {
+        fluid_let! {
+            static NUMBER: i32;
+            static STRING: String;
+        }
+        export_from_str("registry::export_and_bind::number", &NUMBER);
+        export_from_str("registry::export_and_bind::string", &STRING);
+
+        let number = lookup("registry::export_and_bind::number").unwrap();
+        let string = lookup("registry::export_and_bind::string").unwrap();
+        assert_eq!(number.type_name, any::type_name::<i32>());
+        assert!(lookup("registry::export_and_bind::missing").is_none());
+
+        let values: Vec<Assignment> = vec![
+            (number, Box::new(42)),
+            (string, Box::new(String::from("text"))),
+        ];
+        let result = bind_all(&values, || (NUMBER.copied(), STRING.cloned()));
+        assert_eq!(result, Ok((Some(42), Some(String::from("text")))));
+        assert_eq!(NUMBER.copied(), None);
+    }
+
+    #[test]
+    fn bind_errors() {
+        fluid_let! {
+            static DENIED: i32; shadow = deny;
+            static FINAL: i32;
+        }
+        export_from_str("registry::bind_errors::denied", &DENIED);
+        export_from_str("registry::bind_errors::final", &FINAL);
+
+        let denied = lookup("registry::bind_errors::denied").unwrap();
+        let last: Vec<Assignment> = vec![(denied.clone(), Box::new(1)), (denied, Box::new(2))];
+        assert_eq!(bind_all(&last, || DENIED.copied()), Ok(Some(2)));
+        let result = DENIED.set(0, || bind_all(&last, || unreachable!()));
+        assert_eq!(
+            result,
+            Err(("registry::bind_errors::denied", SetError::Shadowing))
+        );
+
+        let values: Vec<Assignment> =
+            vec![(lookup("registry::bind_errors::final").unwrap(), Box::new(1))];
+        let result = FINAL.set_final(0, || bind_all(&values, || unreachable!()));
+        assert_eq!(
+            result,
+            Err(("registry::bind_errors::final", SetError::Final))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "already exported")]
+    fn duplicate_names() {
+        fluid_let! {
+            static NUMBER_1: i32;
+            static NUMBER_2: i32;
+        }
+        export_from_str("registry::duplicate_names", &NUMBER_1);
+        export_from_str("registry::duplicate_names", &NUMBER_1);
+        export_from_str("registry::duplicate_names", &NUMBER_2);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Process-wide values shared by all threads.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// An atomically replaceable, optional, shared value.
+///
+/// Readers never block on other readers: they take a reference-counted snapshot
+/// of the current value. Writers swap in a new value and retire the old one.
+/// Retired values are freed by the last reader in the middle of taking a snapshot,
+/// or by the writer if there are no such readers.
+pub(crate) struct Shared<T: ?Sized> {
+    current: AtomicPtr<Arc<T>>,
+    readers: AtomicUsize,
+    // Set while there are retired values, so that readers check them only when needed.
+    pending: AtomicBool,
+    // Readers might still be looking at the box itself, so it is retired as a whole.
+    #[allow(clippy::vec_box)]
+    retired: Mutex<Vec<Box<Arc<T>>>>,
+}
+
+impl<T: ?Sized> Shared<T> {
+    /// Makes a new empty value.
+    pub(crate) const fn empty() -> Self {
+        Shared {
+            current: AtomicPtr::new(ptr::null_mut()),
+            readers: AtomicUsize::new(0),
+            pending: AtomicBool::new(false),
+            retired: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes a snapshot of the current value, if any.
+    pub(crate) fn load(&self) -> Option<Arc<T>> {
+        // Fast path for the common case when the value has never been set.
+        if self.current.load(Ordering::Acquire).is_null() && !self.pending.load(Ordering::Acquire) {
+            return None;
+        }
+        self.enter();
+        let current = self.current.load(Ordering::SeqCst);
+        // This is safe because retired values are not freed while there are
+        // readers which might have loaded them before the swap.
+        let snapshot = unsafe { current.as_ref() }.cloned();
+        self.leave();
+        snapshot
+    }
+
+    /// Replaces the current value, returning the previous one.
+    pub(crate) fn store(&self, value: Option<Arc<T>>) -> Option<Arc<T>> {
+        let new = match value {
+            Some(value) => Box::into_raw(Box::new(value)),
+            None => ptr::null_mut(),
+        };
+        let old = self.current.swap(new, Ordering::SeqCst);
+        // This is safe because only store() takes pointers out of "current",
+        // and each of them is taken exactly once.
+        let previous = unsafe { old.as_ref() }.cloned();
+        if !old.is_null() {
+            let mut retired = self.retired.lock().unwrap_or_else(|e| e.into_inner());
+            retired.push(unsafe { Box::from_raw(old) });
+            self.pending.store(true, Ordering::SeqCst);
+        }
+        self.reclaim();
+        previous
+    }
+
+    fn enter(&self) {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn leave(&self) {
+        if self.readers.fetch_sub(1, Ordering::SeqCst) == 1 && self.pending.load(Ordering::SeqCst) {
+            self.reclaim();
+        }
+    }
+
+    /// Frees retired values if nobody can be using them.
+    fn reclaim(&self) {
+        let mut retired = self.retired.lock().unwrap_or_else(|e| e.into_inner());
+        // Readers that start after this point will observe the new value, so if there
+        // are no readers right now then nobody can be using any of the retired ones.
+        if self.readers.load(Ordering::SeqCst) != 0 {
+            return;
+        }
+        let freed = mem::take(&mut *retired);
+        self.pending.store(false, Ordering::SeqCst);
+        // Values are dropped outside of the lock as their destructors might use this value.
+        drop(retired);
+        drop(freed);
+    }
+}
+
+impl<T: ?Sized> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let current = *self.current.get_mut();
+        if !current.is_null() {
+            // This is safe because we have exclusive access to the value.
+            drop(unsafe { Box::from_raw(current) });
+        }
+    }
+}
+
+// Shared<T> hands out Arc<T> to other threads, which requires the same bounds as Arc.
+unsafe impl<T: ?Sized + Send + Sync> Send for Shared<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Shared<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn load_store() {
+        let shared = Shared::empty();
+        assert_eq!(shared.load(), None);
+        assert_eq!(shared.store(Some(Arc::new(1))), None);
+        assert_eq!(shared.load(), Some(Arc::new(1)));
+        let snapshot = shared.load();
+        assert_eq!(shared.store(Some(Arc::new(2))), Some(Arc::new(1)));
+        assert_eq!(snapshot, Some(Arc::new(1)));
+        assert_eq!(shared.store(None), Some(Arc::new(2)));
+        assert_eq!(shared.load(), None);
+    }
+
+    #[test]
+    fn concurrent_access() {
+        static SHARED: Shared<String> = Shared::empty();
+
+        let writer = thread::spawn(|| {
+            for i in 0..1000 {
+                SHARED.store(Some(Arc::new(i.to_string())));
+            }
+        });
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..1000 {
+                        if let Some(value) = SHARED.load() {
+                            assert!(value.parse::<i32>().is_ok());
+                        }
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(SHARED.load().as_deref().map(|s| &s[..]), Some("999"));
+    }
+
+    #[test]
+    fn reclamation() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let shared = Shared::empty();
+        shared.store(Some(Arc::new(Counted)));
+        shared.store(Some(Arc::new(Counted)));
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+
+        // A reader in the middle of taking a snapshot frees the value retired meanwhile.
+        shared.enter();
+        shared.store(None);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        shared.leave();
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+
+        let shared = Arc::new(Shared::empty());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        shared.load();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..1000 {
+            shared.store(Some(Arc::new(Counted)));
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2 + 999);
+        drop(shared);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2 + 1000);
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Injectable clock.
+//!
+//! Time-dependent code can use [`now`], [`instant`], and [`sleep`] which consult
+//! the current [`Clock`]. By default that is the [`SystemClock`]. Tests can bind
+//! a [`MockClock`] with [`with_clock`] and advance time manually:
+//!
+//! ```
+//! use std::time::{Duration, SystemTime};
+//!
+//! use fluid_let::time::{self, MockClock};
+//!
+//! fn is_expired(created: SystemTime) -> bool {
+//!     time::now() > created + Duration::from_secs(60)
+//! }
+//!
+//! let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+//! time::with_clock(&clock, || {
+//!     let created = time::now();
+//!     assert!(!is_expired(created));
+//!
+//!     // Sleeping with mock clock advances its time without waiting.
+//!     time::sleep(Duration::from_secs(90));
+//!     assert!(is_expired(created));
+//! });
+//! ```
+//!
+//! Just like dynamic variables, clocks are bound per thread. Threads spawned with
+//! [`scope`] use the clock of the spawning thread. Other threads need to bind it
+//! themselves.
+//!
+//! [`now`]: fn.now.html
+//! [`instant`]: fn.instant.html
+//! [`sleep`]: fn.sleep.html
+//! [`with_clock`]: fn.with_clock.html
+//! [`scope`]: fn.scope.html
+//! [`Clock`]: trait.Clock.html
+//! [`SystemClock`]: struct.SystemClock.html
+//! [`MockClock`]: struct.MockClock.html
+
+use std::mem;
+use std::sync::Mutex;
+use std::thread::{self, ScopedJoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::fluid_let;
+
+/// Source of time.
+///
+/// Clocks must be `Sync` so that they can be used by spawned threads.
+pub trait Clock: Sync {
+    /// Returns current system time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns current monotonic time.
+    fn instant(&self) -> Instant;
+
+    /// Waits until given time passes.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Clock of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Clock which is advanced manually.
+///
+/// The time stands still unless the clock is advanced explicitly or by sleeping.
+#[derive(Debug)]
+pub struct MockClock {
+    start: SystemTime,
+    start_instant: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Makes a new clock showing given system time.
+    pub fn new(start: SystemTime) -> Self {
+        MockClock {
+            start,
+            start_instant: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Advances the time of the clock.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.start + self.elapsed()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+fluid_let!(static CLOCK: for<'a> &'a dyn Clock);
+
+/// Uses `clock` for the dynamic extent of the closure.
+pub fn with_clock<R>(clock: &dyn Clock, f: impl FnOnce() -> R) -> R {
+    CLOCK.set(clock, f)
+}
+
+/// Calls the closure with the current clock.
+fn current_clock<R>(f: impl FnOnce(&dyn Clock) -> R) -> R {
+    CLOCK.get(|clock| match clock {
+        Some(clock) => f(*clock),
+        None => f(&SystemClock),
+    })
+}
+
+/// Scope for spawning threads which use the same clock.
+///
+/// Created by [`scope`](fn.scope.html).
+pub struct Scope<'scope, 'env: 'scope> {
+    scope: &'scope thread::Scope<'scope, 'env>,
+    clock: &'scope dyn Clock,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a scoped thread which uses the clock bound when the scope was created.
+    pub fn spawn<T>(&self, f: impl FnOnce() -> T + Send + 'scope) -> ScopedJoinHandle<'scope, T>
+    where
+        T: Send + 'scope,
+    {
+        let clock = self.clock;
+        self.scope.spawn(move || with_clock(clock, f))
+    }
+}
+
+/// Creates a scope for spawning threads which use the current clock.
+///
+/// This is like `std::thread::scope`: all threads spawned in the scope
+/// are joined before this function returns.
+pub fn scope<'env, T>(f: impl for<'scope> FnOnce(&Scope<'scope, 'env>) -> T) -> T {
+    current_clock(|clock| {
+        // This is safe because all threads using the clock are joined
+        // before thread::scope() returns, while the clock is still alive.
+        let clock: &'static dyn Clock = unsafe { mem::transmute(clock) };
+        thread::scope(|scope| f(&Scope { scope, clock }))
+    })
+}
+
+/// Returns current system time according to the current clock.
+pub fn now() -> SystemTime {
+    current_clock(|clock| clock.now())
+}
+
+/// Returns current monotonic time according to the current clock.
+pub fn instant() -> Instant {
+    current_clock(|clock| clock.instant())
+}
+
+/// Waits until given time passes according to the current clock.
+pub fn sleep(duration: Duration) {
+    current_clock(|clock| clock.sleep(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let clock = MockClock::new(start);
+        let before = with_clock(&clock, || {
+            assert_eq!(now(), start);
+            let before = instant();
+            sleep(Duration::from_secs(5));
+            assert_eq!(instant() - before, Duration::from_secs(5));
+            scope(|scope| {
+                scope.spawn(|| sleep(Duration::from_secs(1)));
+            });
+            // Threads spawned directly use the system clock.
+            thread::spawn(move || assert!(now() > start + Duration::from_secs(3600)))
+                .join()
+                .unwrap();
+            assert_eq!(now(), start + Duration::from_secs(6));
+            before
+        });
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(clock.instant() - before, Duration::from_secs(10));
+        assert!(now() > start);
+    }
+}
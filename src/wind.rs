@@ -0,0 +1,96 @@
+// Copyright (c) 2021, ilammy
+// Licensed under MIT license (see LICENSE)
+
+//! Entry and exit hooks around dynamic extents.
+
+/// Calls `body` between `before` and `after`.
+///
+/// This is Scheme's `dynamic-wind`: `after` is called when control leaves the dynamic
+/// extent of `body`, either by returning or by panicking, so resources acquired by
+/// `before` are released reliably:
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::panic::{self, AssertUnwindSafe};
+///
+/// use fluid_let::wind;
+///
+/// let locked = Cell::new(false);
+///
+/// let result = panic::catch_unwind(AssertUnwindSafe(|| {
+///     wind(
+///         || locked.set(true),
+///         || panic!("something went wrong"),
+///         || locked.set(false),
+///     )
+/// }));
+///
+/// assert!(result.is_err());
+/// assert!(!locked.get());
+/// ```
+///
+/// If `before` panics then neither `body` nor `after` is called.
+/// Note that panicking in `after` while the thread is already panicking aborts the process.
+///
+/// Dynamic variables can have similar hooks, see [`fluid_let!`](macro.fluid_let.html).
+pub fn wind<R>(before: impl FnOnce(), body: impl FnOnce() -> R, after: impl FnOnce()) -> R {
+    before();
+    let _after_ = After(Some(after));
+    body()
+}
+
+/// Calls the closure when dropped.
+struct After<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Drop for After<F> {
+    fn drop(&mut self) {
+        if let Some(after) = self.0.take() {
+            after();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn enter_and_exit() {
+        let log = RefCell::new(Vec::new());
+        let result = wind(
+            || log.borrow_mut().push("before"),
+            || {
+                log.borrow_mut().push("body");
+                42
+            },
+            || log.borrow_mut().push("after"),
+        );
+        assert_eq!(result, 42);
+        assert_eq!(*log.borrow(), ["before", "body", "after"]);
+
+        log.borrow_mut().clear();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            wind(
+                || log.borrow_mut().push("before"),
+                || panic!("oops"),
+                || log.borrow_mut().push("after"),
+            )
+        }));
+        assert!(result.is_err());
+        assert_eq!(*log.borrow(), ["before", "after"]);
+
+        log.borrow_mut().clear();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            wind(
+                || panic!("oops"),
+                || log.borrow_mut().push("body"),
+                || log.borrow_mut().push("after"),
+            )
+        }));
+        assert!(result.is_err());
+        assert!(log.borrow().is_empty());
+    }
+}